@@ -1,55 +1,125 @@
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::info;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use wgpu::SurfaceError;
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
-use winit::event::WindowEvent;
-use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::window::{Window, WindowAttributes, WindowId};
+use crate::errors::NimbusError;
+use crate::render::config::RendererConfig;
+use crate::render::controller::CameraController;
 use crate::render::renderer::{FrameContext, Renderer};
 use crate::render::scene::Scene;
 
-pub struct App {
-    proxy: Option<EventLoopProxy<State<'static>>>,
-    state: Option<State<'static>>
+const MAX_FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// Hooks the windowed [`App`] calls to build the scene and around every frame.
+pub trait AppHandler {
+    fn window_attributes(&self) -> WindowAttributes {
+        Window::default_attributes()
+            .with_title("Nimbus Renderer")
+            .with_resizable(true)
+    }
+
+    /// Directories whose `.wgsl` files are registered with the shader library and
+    /// reloaded when they change on disk. Nothing is watched by default.
+    fn shader_directories(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    fn init(&mut self, renderer: &mut Renderer) -> anyhow::Result<Scene>;
+
+    /// Controller that receives window input and moves the scene camera every frame.
+    fn camera_controller(&mut self) -> Option<&mut dyn CameraController> {
+        None
+    }
+
+    /// Called for every window event, after the camera controller has seen it.
+    fn window_event(&mut self, _scene: &mut Scene, _event: &WindowEvent) {}
+
+    /// Called after the frame has begun, before the scene is submitted.
+    fn before_frame(&mut self, _renderer: &mut Renderer, _scene: &mut Scene, _frame: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the frame was submitted. Returning `false` closes the app.
+    fn after_frame(&mut self, _renderer: &mut Renderer, _scene: &mut Scene, _frame: u64) -> anyhow::Result<bool> {
+        Ok(true)
+    }
 }
 
-impl App {
-    pub fn new(event_loop: &EventLoop<State>) -> Self {
-        let proxy = Some(event_loop.create_proxy());
+pub struct App<H: AppHandler> {
+    state: Option<State<'static>>,
+    config: RendererConfig,
+    handler: H,
+}
+
+impl<H: AppHandler> App<H> {
+    pub fn new(config: RendererConfig, handler: H) -> Self {
         Self {
             state: None,
-            proxy
+            config,
+            handler,
         }
     }
+
+    pub fn run(config: RendererConfig, handler: H) -> anyhow::Result<()> {
+        let event_loop = EventLoop::new()?;
+        let mut app = App::new(config, handler);
+        event_loop.run_app(&mut app)?;
+        Ok(())
+    }
 }
 
-impl ApplicationHandler<State<'static>> for App {
+impl<H: AppHandler> ApplicationHandler for App<H> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window = Arc::new(
-            event_loop
-                .create_window(Window::default_attributes()
-                    .with_title("Nimbus Renderer")
-                    .with_resizable(true)
-                )
-                .unwrap(),
-        );
-
-        let state = pollster::block_on(State::new(window.clone())).unwrap();
-        self.state = Some(state);
+        let window = match event_loop.create_window(self.handler.window_attributes()) {
+            Ok(window) => Arc::new(window),
+            Err(err) => {
+                error!("Failed to create a window: {err}");
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let state = pollster::block_on(State::new(window.clone(), self.config.clone(), &mut self.handler));
+        match state {
+            Ok(state) => self.state = Some(state),
+            Err(err) => {
+                error!("Failed to start: {err:#}");
+                event_loop.exit();
+                return;
+            }
+        }
 
         window.request_redraw();
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
-        let state = self.state.as_mut().unwrap();
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        if let Some(controller) = self.handler.camera_controller() {
+            controller.window_event(&event);
+        }
+        self.handler.window_event(&mut state.scene, &event);
         match event {
             WindowEvent::CloseRequested => {
                 info!("The close button was pressed; stopping");
                 event_loop.exit();
             }
             WindowEvent::RedrawRequested => {
-                state.render().unwrap();
-                state.window.request_redraw();
+                match state.render(&mut self.handler) {
+                    Ok(true) => state.window.request_redraw(),
+                    Ok(false) => event_loop.exit(),
+                    Err(err) => {
+                        error!("Rendering failed; stopping: {err:#}");
+                        event_loop.exit();
+                    }
+                }
             }
             WindowEvent::Resized(PhysicalSize {width, height}) => {
                 state.resize(width, height);
@@ -57,6 +127,15 @@ impl ApplicationHandler<State<'static>> for App {
             _ => (),
         }
     }
+
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _device_id: DeviceId, event: DeviceEvent) {
+        if self.state.is_none() {
+            return;
+        }
+        if let Some(controller) = self.handler.camera_controller() {
+            controller.device_event(&event);
+        }
+    }
 }
 
 pub struct State<'window> {
@@ -64,42 +143,94 @@ pub struct State<'window> {
     scene: Scene,
     window: Arc<Window>,
     is_surface_configured: bool,
-    frame_context: FrameContext
+    frame_context: FrameContext,
+    frame: u64,
+    last_frame: Instant,
 }
 
 impl<'window> State<'window> {
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
-        let renderer = Renderer::new(window.clone()).await?;
-        let scene = Scene::new();
+    pub async fn new(
+        window: Arc<Window>,
+        config: RendererConfig,
+        handler: &mut impl AppHandler,
+    ) -> anyhow::Result<Self> {
+        let mut renderer = Renderer::new(window.clone(), config).await?;
+        for shader_dir in handler.shader_directories() {
+            match renderer.add_shader_directory(&shader_dir) {
+                Ok(count) => info!("Watching {count} shaders in {}", shader_dir.display()),
+                Err(err) => warn!("Failed to watch shaders in {}: {err}", shader_dir.display()),
+            }
+        }
+        let scene = handler.init(&mut renderer)?;
         Ok(Self {
             renderer,
             scene,
             window,
             frame_context: FrameContext::default(),
-            is_surface_configured: false
+            is_surface_configured: false,
+            frame: 0,
+            last_frame: Instant::now(),
         })
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.renderer.surface_config.width = width;
-            self.renderer.surface_config.height = height;
-            self.renderer.surface.configure(&self.renderer.device, &self.renderer.surface_config);
-            self.is_surface_configured = true;
+        // A minimized window reports a zero size; stop rendering until it comes back.
+        self.is_surface_configured = width > 0 && height > 0;
+        if self.is_surface_configured {
+            self.renderer.resize(width, height);
+            self.scene.camera.set_aspect(width as f32 / height as f32);
         }
     }
 
-    pub fn render(&mut self) -> anyhow::Result<()> {
+    /// Renders one frame and returns whether the app should keep running.
+    pub fn render(&mut self, handler: &mut impl AppHandler) -> anyhow::Result<bool> {
+        // Clamped so a stall (e.g. dragging the window) doesn't teleport the camera.
+        let now = Instant::now();
+        let dt = (now - self.last_frame).min(MAX_FRAME_TIME);
+        self.last_frame = now;
+
         if !self.is_surface_configured {
-            return Ok(());
+            return Ok(true);
         }
-        
-        self.renderer.begin_frame(&mut self.frame_context)?;
 
+        match self.renderer.begin_frame(&mut self.frame_context) {
+            Ok(()) => {}
+            Err(NimbusError::SurfaceError(SurfaceError::Lost | SurfaceError::Outdated)) => {
+                let PhysicalSize { width, height } = self.window.inner_size();
+                info!("Surface lost or outdated; reconfiguring at {width}x{height}");
+                self.resize(width, height);
+                return Ok(true);
+            }
+            Err(NimbusError::DeviceLost) => {
+                warn!("Recreating GPU resources after device loss");
+                pollster::block_on(self.renderer.recover_device())?;
+                self.scene.recreate_gpu_resources(&mut self.renderer);
+                return Ok(true);
+            }
+            Err(NimbusError::SurfaceError(err @ (SurfaceError::Timeout | SurfaceError::Other))) => {
+                warn!("Skipping frame: {err}");
+                return Ok(true);
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(controller) = handler.camera_controller() {
+            // Authored cameras follow their node. The controller still consumes its input
+            // so it doesn't pile up until the free camera is back.
+            let mut camera = self.scene.camera;
+            controller.update(&mut camera, dt);
+            if self.scene.active_camera.is_none() {
+                self.scene.camera = camera;
+            }
+        }
+        handler.before_frame(&mut self.renderer, &mut self.scene, self.frame)?;
+        self.scene.update_active_camera();
         self.renderer.submit_camera(&self.scene.camera);
         self.scene.render(&mut self.renderer, &mut self.frame_context);
-
         self.renderer.end_frame(&mut self.frame_context);
-        Ok(())
+
+        let running = handler.after_frame(&mut self.renderer, &mut self.scene, self.frame)?;
+        self.frame += 1;
+        Ok(running)
     }
 }
@@ -13,4 +13,59 @@ pub enum NimbusError {
 
     #[error("Surface error: {0}")]
     SurfaceError(#[from] wgpu::SurfaceError),
-}
\ No newline at end of file
+
+    #[error("Device poll failed: {0}")]
+    PollError(#[from] wgpu::PollError),
+
+    #[error("Buffer map failed: {0}")]
+    BufferAsyncError(#[from] wgpu::BufferAsyncError),
+
+    #[error("glTF import failed: {0}")]
+    GltfError(#[from] gltf::Error),
+
+    #[error("Shader preprocessing failed: {0}")]
+    ShaderPreprocessError(#[from] crate::render::preprocessor::PreprocessError),
+
+    #[error("Shader compilation failed for material \"{material}\" at {location}: {message}")]
+    ShaderCompileError {
+        material: String,
+        location: String,
+        message: String,
+    },
+
+    #[error("Pipeline creation failed for material \"{material}\": {message}")]
+    PipelineCreationError { material: String, message: String },
+
+    #[error("Bind group creation failed for material \"{material}\": {message}")]
+    BindGroupCreationError { material: String, message: String },
+
+    #[error("Invalid renderer config: {0}")]
+    ConfigError(#[from] crate::render::config::ConfigError),
+
+    #[error("No adapter matching \"{0}\" was found")]
+    AdapterNotFound(String),
+
+    #[error("GPU device was lost")]
+    DeviceLost,
+
+    #[error("Sample count {0} is not supported by the adapter for the current target formats")]
+    UnsupportedSampleCount(u32),
+
+    #[error("{format:?} is not a depth format usable with {sample_count}x MSAA on this device")]
+    UnsupportedDepthFormat {
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    },
+
+    #[error("Reading back {0:?} frames is not supported")]
+    UnsupportedReadbackFormat(wgpu::TextureFormat),
+
+    #[error("Readback was cancelled before the buffer was mapped")]
+    ReadbackCancelled,
+
+    #[error("Renderer has no offscreen target to read back from")]
+    NoOffscreenTarget,
+
+    #[error("The surface does not support copying frames out for capture")]
+    CaptureUnsupported,
+}
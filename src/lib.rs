@@ -0,0 +1,27 @@
+mod errors;
+mod render;
+#[cfg(feature = "windowing")]
+mod app;
+
+#[cfg(feature = "windowing")]
+pub use app::{App, AppHandler};
+pub use errors::NimbusError;
+pub use render::camera::{Camera, Projection};
+pub use render::config::{
+    Backend, ColorFormatConfig, ConfigError, PowerPreferenceConfig, PresentModeConfig, RendererConfig,
+};
+#[cfg(feature = "windowing")]
+pub use render::controller::{CameraController, FlyController, OrbitController};
+pub use render::drawable::Drawable;
+pub use render::importer::GltfImporter;
+pub use render::material::{
+    AlphaMode, BaseColorType, CustomMaterial, CustomResource, Emissive, Material, MaterialFactors, MaterialId,
+    MaterialTexture, MaterialType, MetallicRoughnessType, NormalTexture, OcclusionTexture, TextureSlot, TextureSource,
+};
+pub use render::mesh::{Mesh, Vertex};
+pub use render::pipeline::PipelineCacheStats;
+pub use render::preprocessor::PreprocessError;
+pub use render::renderer::{FrameContext, Renderer};
+pub use render::scene::{NodeTransform, Scene, SceneNode};
+
+pub type Result<T> = std::result::Result<T, NimbusError>;
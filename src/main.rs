@@ -1,18 +1,109 @@
-use crate::app::App;
-use crate::errors::NimbusError;
-use winit::event_loop::EventLoop;
+use crate::viewer::{Viewer, ViewerOptions};
+use clap::Parser;
+use nimbus::{App, Backend, ColorFormatConfig, PowerPreferenceConfig, PresentModeConfig, RendererConfig};
+use std::path::PathBuf;
 
-mod app;
-mod render;
-mod errors;
+mod viewer;
 
-pub type Result<T> = std::result::Result<T, NimbusError>;
+#[derive(Parser, Debug)]
+#[command(version, about = "Nimbus glTF viewer")]
+struct Args {
+    #[command(flatten)]
+    viewer: ViewerOptions,
+
+    /// Renderer config file (.toml or .ron); flags below override its values.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Backends to try: vulkan, metal, dx12 or gl.
+    #[arg(long, value_delimiter = ',')]
+    backend: Vec<Backend>,
+
+    /// Use the first adapter whose name contains this string.
+    #[arg(long)]
+    adapter: Option<String>,
+
+    /// Adapter power preference: default, low-power or high-performance.
+    #[arg(long)]
+    power_preference: Option<PowerPreferenceConfig>,
+
+    /// Present mode: vsync, mailbox or immediate. Falls back to vsync if unsupported.
+    #[arg(long)]
+    present_mode: Option<PresentModeConfig>,
+
+    /// Color format of the surface: sdr or hdr.
+    #[arg(long)]
+    color_format: Option<ColorFormatConfig>,
+
+    #[arg(long)]
+    frame_latency: Option<u32>,
+
+    /// Additional wgpu features to require, e.g. POLYGON_MODE_LINE.
+    #[arg(long, value_delimiter = ',')]
+    feature: Vec<String>,
+
+    #[arg(long)]
+    validation: Option<bool>,
+
+    #[arg(long)]
+    reversed_z: Option<bool>,
+
+    /// MSAA sample count: 1, 2, 4 or 8.
+    #[arg(long)]
+    msaa: Option<u32>,
+}
+
+impl Args {
+    fn renderer_config(&self) -> nimbus::Result<RendererConfig> {
+        let mut config = match &self.config {
+            Some(path) => RendererConfig::load(path)?,
+            None => RendererConfig::default(),
+        };
+
+        if !self.backend.is_empty() {
+            config.backends = self.backend.clone();
+        }
+        if let Some(adapter) = &self.adapter {
+            config.adapter = Some(adapter.clone());
+        }
+        if let Some(power_preference) = self.power_preference {
+            config.power_preference = power_preference;
+        }
+        if let Some(present_mode) = self.present_mode {
+            config.present_mode = present_mode;
+        }
+        if let Some(color_format) = self.color_format {
+            config.color_format = color_format;
+        }
+        if let Some(frame_latency) = self.frame_latency {
+            config.frame_latency = frame_latency;
+        }
+        config.features.extend(self.feature.iter().cloned());
+        if let Some(validation) = self.validation {
+            config.validation = validation;
+        }
+        if let Some(reversed_z) = self.reversed_z {
+            config.reversed_z = reversed_z;
+        }
+        if let Some(sample_count) = self.msaa {
+            config.sample_count = sample_count;
+        }
+
+        // Fail on invalid settings before a window is opened.
+        config.validate()?;
+        Ok(config)
+    }
+}
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    
-    let event_loop = EventLoop::with_user_event().build()?;
-    let mut app = App::new(&event_loop);
-    event_loop.run_app(&mut app)?;
-    Ok(())
-}
\ No newline at end of file
+
+    let args = Args::parse();
+    let config = args.renderer_config()?;
+
+    if args.viewer.headless {
+        return args.viewer.run_headless(config);
+    }
+
+    App::run(config, Viewer::new(args.viewer))
+}
@@ -1,5 +1,154 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{perspective, Deg, EuclideanSpace, Matrix4, Point3, Vector3};
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Transform, Vector3};
+
+/// Maps view space to clip space with wgpu's `0..1` depth range. With `reversed_z` the
+/// near plane maps to depth 1 and the far plane to 0, which spreads depth precision far
+/// more evenly but needs a depth buffer cleared to 0 and a `Greater` depth test.
+/// `Renderer::submit_camera` replaces the flag with the renderer's own setting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// The horizontal field of view follows the camera's aspect ratio, so the aspect
+    /// ratio authored on a glTF perspective camera is ignored.
+    Perspective {
+        /// Vertical field of view in degrees.
+        fov_y: f32,
+        z_near: f32,
+        /// `None` places the far plane at infinity.
+        z_far: Option<f32>,
+        reversed_z: bool,
+    },
+    /// Parallel projection showing `height` world units vertically; the width follows
+    /// the aspect ratio.
+    Orthographic {
+        height: f32,
+        z_near: f32,
+        z_far: f32,
+        reversed_z: bool,
+    },
+    /// Parallel projection of an explicit, possibly off-center view volume, e.g. for
+    /// shadow maps, a fixed 2D viewport or an imported glTF orthographic camera, whose
+    /// `xmag` and `ymag` become the half extents. The aspect ratio is ignored.
+    OrthographicOffCenter {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        z_near: f32,
+        z_far: f32,
+        reversed_z: bool,
+    },
+    /// Used as is; the aspect ratio is ignored.
+    Custom(Matrix4<f32>),
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_y: 45.0,
+            z_near: 0.1,
+            z_far: Some(100.0),
+            reversed_z: false,
+        }
+    }
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective {
+                fov_y,
+                z_near,
+                z_far,
+                reversed_z,
+            } => {
+                let focal = 1.0 / (Rad::from(Deg(fov_y)).0 / 2.0).tan();
+                let (depth_scale, depth_offset) = match (z_far, reversed_z) {
+                    (Some(z_far), false) => (z_far / (z_near - z_far), z_near * z_far / (z_near - z_far)),
+                    (Some(z_far), true) => (z_near / (z_far - z_near), z_near * z_far / (z_far - z_near)),
+                    (None, false) => (-1.0, -z_near),
+                    (None, true) => (0.0, z_near),
+                };
+                Matrix4::new(
+                    focal / aspect, 0.0, 0.0, 0.0,
+                    0.0, focal, 0.0, 0.0,
+                    0.0, 0.0, depth_scale, -1.0,
+                    0.0, 0.0, depth_offset, 0.0,
+                )
+            }
+            Projection::Orthographic {
+                height,
+                z_near,
+                z_far,
+                reversed_z,
+            } => {
+                let half_width = height * aspect / 2.0;
+                orthographic(-half_width, half_width, -height / 2.0, height / 2.0, z_near, z_far, reversed_z)
+            }
+            Projection::OrthographicOffCenter {
+                left,
+                right,
+                bottom,
+                top,
+                z_near,
+                z_far,
+                reversed_z,
+            } => orthographic(left, right, bottom, top, z_near, z_far, reversed_z),
+            Projection::Custom(matrix) => matrix,
+        }
+    }
+
+    /// The same projection with its depth direction set to `reversed_z`. Reversed
+    /// perspective projections also get an infinite far plane, which reversed-Z keeps
+    /// precise. Custom matrices are returned unchanged and must match the depth test.
+    pub fn with_reversed_z(&self, reversed_z: bool) -> Projection {
+        let mut projection = *self;
+        match &mut projection {
+            Projection::Perspective {
+                z_far,
+                reversed_z: reversed,
+                ..
+            } => {
+                *reversed = reversed_z;
+                if reversed_z {
+                    *z_far = None;
+                }
+            }
+            Projection::Orthographic {
+                reversed_z: reversed, ..
+            }
+            | Projection::OrthographicOffCenter {
+                reversed_z: reversed, ..
+            } => *reversed = reversed_z,
+            Projection::Custom(_) => (),
+        }
+        projection
+    }
+}
+
+fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    z_near: f32,
+    z_far: f32,
+    reversed_z: bool,
+) -> Matrix4<f32> {
+    let width = right - left;
+    let height = top - bottom;
+    let depth = z_far - z_near;
+    let (depth_scale, depth_offset) = if reversed_z {
+        (1.0 / depth, z_far / depth)
+    } else {
+        (-1.0 / depth, -z_near / depth)
+    };
+    Matrix4::new(
+        2.0 / width, 0.0, 0.0, 0.0,
+        0.0, 2.0 / height, 0.0, 0.0,
+        0.0, 0.0, depth_scale, 0.0,
+        -(right + left) / width, -(top + bottom) / height, depth_offset, 1.0,
+    )
+}
 
 #[derive(Copy, Clone)]
 pub struct Camera {
@@ -7,30 +156,112 @@ pub struct Camera {
     target: Point3<f32>,
     up: Vector3<f32>,
     aspect: f32,
-    fov_y: f32,
-    z_near: f32,
-    z_far: f32,
+    projection: Projection,
 }
 
 impl Default for Camera {
     fn default() -> Self {
         Camera {
-            eye: Point3::origin(),
+            eye: Point3::new(0.0, 0.0, 3.0),
             target: Point3::origin(),
-            up: Vector3::unit_x(),
+            up: Vector3::unit_y(),
             aspect: 16.0 / 9.0,
-            fov_y: 45.0,
-            z_near: 0.1,
-            z_far: 100.0
+            projection: Projection::default(),
         }
     }
 }
 
 impl Camera {
-    pub fn projection(&self) -> Matrix4<f32> {
-        perspective(Deg(self.fov_y), self.aspect, self.z_near, self.z_far)
+    pub fn new(
+        eye: Point3<f32>,
+        target: Point3<f32>,
+        up: Vector3<f32>,
+        aspect: f32,
+        fov_y: f32,
+        z_near: f32,
+        z_far: f32,
+    ) -> Self {
+        Self {
+            eye,
+            target,
+            up,
+            aspect,
+            projection: Projection::Perspective {
+                fov_y,
+                z_near,
+                z_far: Some(z_far),
+                reversed_z: false,
+            },
+        }
+    }
+
+    /// Camera placed by a node transform, looking down the node's -Z axis with +Y up
+    /// as glTF cameras do.
+    pub fn from_transform(transform: Matrix4<f32>, aspect: f32, projection: Projection) -> Self {
+        let eye = transform.transform_point(Point3::origin());
+        let forward = transform.transform_vector(-Vector3::unit_z()).normalize();
+        Self {
+            eye,
+            target: eye + forward,
+            up: transform.transform_vector(Vector3::unit_y()).normalize(),
+            aspect,
+            projection,
+        }
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.eye
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.target
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.up
+    }
+
+    pub fn look_at(&mut self, eye: Point3<f32>, target: Point3<f32>) {
+        self.eye = eye;
+        self.target = target;
+    }
+
+    pub fn set_eye(&mut self, eye: Point3<f32>) {
+        self.eye = eye;
+    }
+
+    pub fn set_target(&mut self, target: Point3<f32>) {
+        self.target = target;
+    }
+
+    pub fn set_up(&mut self, up: Vector3<f32>) {
+        self.up = up;
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
     }
-    
+
+    pub fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        self.projection.matrix(self.aspect)
+    }
+
     pub fn view(&self) -> Matrix4<f32> {
         Matrix4::look_at_rh(self.eye, self.target, self.up)
     }
@@ -38,22 +269,83 @@ impl Camera {
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, Pod, Zeroable)]
-pub struct CameraUniform {
+pub struct ViewUniform {
     pub view: [[f32; 4]; 4],
     pub projection: [[f32; 4]; 4],
+    pub view_projection: [[f32; 4]; 4],
+    pub inverse_view: [[f32; 4]; 4],
+    pub inverse_projection: [[f32; 4]; 4],
+    pub camera_position: [f32; 4],
+    pub viewport_size: [f32; 2],
+    pub time: f32,
+    pub _padding: f32,
 }
 
-impl CameraUniform {
+impl ViewUniform {
     pub fn new() -> Self {
-        use cgmath::SquareMatrix;
         Self {
             view: Matrix4::identity().into(),
             projection: Matrix4::identity().into(),
+            view_projection: Matrix4::identity().into(),
+            inverse_view: Matrix4::identity().into(),
+            inverse_projection: Matrix4::identity().into(),
+            ..Default::default()
         }
     }
 
-    pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view = camera.view().into();
-        self.projection = camera.projection().into();
+    pub fn update(&mut self, camera: &Camera, viewport_width: u32, viewport_height: u32, time: f32) {
+        let view = camera.view();
+        let projection = camera.projection_matrix();
+
+        self.view = view.into();
+        self.projection = projection.into();
+        self.view_projection = (projection * view).into();
+        self.inverse_view = view.invert().unwrap_or(Matrix4::identity()).into();
+        self.inverse_projection = projection.invert().unwrap_or(Matrix4::identity()).into();
+        self.camera_position = camera.eye.to_homogeneous().into();
+        self.viewport_size = [viewport_width as f32, viewport_height as f32];
+        self.time = time;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Vector4, assert_abs_diff_eq};
+
+    #[test]
+    fn off_center_orthographic_maps_extents_to_clip_space() {
+        let projection = Projection::OrthographicOffCenter {
+            left: 1.0,
+            right: 5.0,
+            bottom: -1.0,
+            top: 3.0,
+            z_near: 0.5,
+            z_far: 10.5,
+            reversed_z: false,
+        };
+        let matrix = projection.matrix(2.0);
+        assert_abs_diff_eq!(matrix * Vector4::new(1.0, -1.0, -0.5, 1.0), Vector4::new(-1.0, -1.0, 0.0, 1.0));
+        assert_abs_diff_eq!(matrix * Vector4::new(5.0, 3.0, -10.5, 1.0), Vector4::new(1.0, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn centered_orthographic_matches_off_center() {
+        let centered = Projection::Orthographic {
+            height: 4.0,
+            z_near: 0.1,
+            z_far: 100.0,
+            reversed_z: true,
+        };
+        let off_center = Projection::OrthographicOffCenter {
+            left: -3.0,
+            right: 3.0,
+            bottom: -2.0,
+            top: 2.0,
+            z_near: 0.1,
+            z_far: 100.0,
+            reversed_z: true,
+        };
+        assert_abs_diff_eq!(centered.matrix(1.5), off_center.matrix(1.5));
     }
 }
@@ -0,0 +1,361 @@
+use serde::de::value::Error as ValueError;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+use wgpu::{Backends, Features, InstanceFlags, Limits, PowerPreference, PresentMode, TextureFormat};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse {path}: {source}")]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("Failed to parse {path}: {source}")]
+    Ron {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+
+    #[error("Unsupported config format {0}, expected a .toml or .ron file")]
+    UnsupportedFormat(PathBuf),
+
+    #[error("Unknown wgpu feature {0}")]
+    UnknownFeature(String),
+
+    #[error("{0}")]
+    InvalidValue(String),
+
+    #[error("Invalid sample count {0}, expected 1, 2, 4 or 8")]
+    InvalidSampleCount(u32),
+}
+
+/// Parses an enum from the name it has in config files, e.g. `high-performance`.
+fn parse_name<T: DeserializeOwned>(name: &str) -> Result<T, ConfigError> {
+    T::deserialize(name.into_deserializer()).map_err(|err: ValueError| ConfigError::InvalidValue(err.to_string()))
+}
+
+macro_rules! impl_from_str {
+    ($($ty:ty),*) => {
+        $(
+            impl FromStr for $ty {
+                type Err = ConfigError;
+
+                fn from_str(name: &str) -> Result<Self, Self::Err> {
+                    parse_name(name)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_str!(Backend, PowerPreferenceConfig, PresentModeConfig, ColorFormatConfig);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+}
+
+impl Backend {
+    pub fn to_backends(self) -> Backends {
+        match self {
+            Backend::Vulkan => Backends::VULKAN,
+            Backend::Metal => Backends::METAL,
+            Backend::Dx12 => Backends::DX12,
+            Backend::Gl => Backends::GL,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerPreferenceConfig {
+    #[default]
+    Default,
+    LowPower,
+    HighPerformance,
+}
+
+impl From<PowerPreferenceConfig> for PowerPreference {
+    fn from(value: PowerPreferenceConfig) -> Self {
+        match value {
+            PowerPreferenceConfig::Default => PowerPreference::None,
+            PowerPreferenceConfig::LowPower => PowerPreference::LowPower,
+            PowerPreferenceConfig::HighPerformance => PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentModeConfig {
+    #[default]
+    Vsync,
+    Mailbox,
+    Immediate,
+}
+
+impl PresentModeConfig {
+    /// Picks the requested mode if the surface supports it, otherwise falls back to
+    /// FIFO which every surface has to support.
+    pub fn select(self, supported: &[PresentMode]) -> PresentMode {
+        let preferred = match self {
+            PresentModeConfig::Vsync => PresentMode::Fifo,
+            PresentModeConfig::Mailbox => PresentMode::Mailbox,
+            PresentModeConfig::Immediate => PresentMode::Immediate,
+        };
+        if supported.contains(&preferred) {
+            preferred
+        } else {
+            PresentMode::Fifo
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFormatConfig {
+    #[default]
+    Sdr,
+    Hdr,
+}
+
+impl ColorFormatConfig {
+    pub fn select(self, supported: &[TextureFormat]) -> Option<TextureFormat> {
+        match self {
+            ColorFormatConfig::Sdr => supported.iter().find(|format| format.is_srgb()).copied(),
+            ColorFormatConfig::Hdr => supported.iter().find(|&&format| format == TextureFormat::Rgba16Float).copied(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererConfig {
+    /// Backends to consider; empty selects the primary backends of the platform.
+    pub backends: Vec<Backend>,
+    /// Case-insensitive substring of the adapter name to use.
+    pub adapter: Option<String>,
+    pub power_preference: PowerPreferenceConfig,
+    pub force_fallback_adapter: bool,
+    pub present_mode: PresentModeConfig,
+    pub color_format: ColorFormatConfig,
+    pub frame_latency: u32,
+    /// wgpu feature names such as `POLYGON_MODE_LINE`, requested on top of the
+    /// features the renderer enables itself.
+    pub features: Vec<String>,
+    pub limits: Option<Limits>,
+    pub validation: bool,
+    /// Render with reversed-Z depth and an infinite far plane for better depth
+    /// precision in large scenes.
+    pub reversed_z: bool,
+    /// MSAA samples per pixel: 1, 2, 4 or 8. A count the adapter can't render with
+    /// falls back to the highest supported one below it.
+    pub sample_count: u32,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            backends: vec![],
+            adapter: None,
+            power_preference: PowerPreferenceConfig::default(),
+            force_fallback_adapter: false,
+            present_mode: PresentModeConfig::default(),
+            color_format: ColorFormatConfig::default(),
+            frame_latency: 2,
+            features: vec![],
+            limits: None,
+            validation: cfg!(debug_assertions),
+            reversed_z: false,
+            sample_count: 4,
+        }
+    }
+}
+
+impl RendererConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let config: Self = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&source).map_err(|source| ConfigError::Toml {
+                path: path.to_owned(),
+                source,
+            })?,
+            Some("ron") => ron::from_str(&source).map_err(|source| ConfigError::Ron {
+                path: path.to_owned(),
+                source,
+            })?,
+            _ => return Err(ConfigError::UnsupportedFormat(path.to_owned())),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the settings that don't depend on the adapter.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if ![1, 2, 4, 8].contains(&self.sample_count) {
+            return Err(ConfigError::InvalidSampleCount(self.sample_count));
+        }
+        self.features()?;
+        Ok(())
+    }
+
+    pub fn backends(&self) -> Backends {
+        if self.backends.is_empty() {
+            return Backends::PRIMARY;
+        }
+        self.backends
+            .iter()
+            .fold(Backends::empty(), |backends, backend| backends | backend.to_backends())
+    }
+
+    pub fn instance_flags(&self) -> InstanceFlags {
+        if self.validation {
+            InstanceFlags::debugging()
+        } else {
+            InstanceFlags::empty()
+        }
+    }
+
+    pub fn features(&self) -> Result<Features, ConfigError> {
+        self.features.iter().try_fold(Features::empty(), |features, name| {
+            Features::from_name(name)
+                .map(|feature| features | feature)
+                .ok_or_else(|| ConfigError::UnknownFeature(name.clone()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `source` to a file in the temp directory that is removed when dropped.
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(name: &str, source: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("nimbus-{}-{name}", std::process::id()));
+            fs::write(&path, source).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn loads_toml() {
+        let file = TempConfig::new(
+            "load.toml",
+            r#"
+                backends = ["vulkan", "gl"]
+                power_preference = "high-performance"
+                present_mode = "mailbox"
+                features = ["POLYGON_MODE_LINE"]
+                sample_count = 2
+            "#,
+        );
+        let config = RendererConfig::load(&file.0).unwrap();
+        assert_eq!(config.backends, [Backend::Vulkan, Backend::Gl]);
+        assert_eq!(config.backends(), Backends::VULKAN | Backends::GL);
+        assert_eq!(config.power_preference, PowerPreferenceConfig::HighPerformance);
+        assert_eq!(config.present_mode, PresentModeConfig::Mailbox);
+        assert_eq!(config.features().unwrap(), Features::POLYGON_MODE_LINE);
+        assert_eq!(config.sample_count, 2);
+        assert_eq!(config.frame_latency, 2);
+    }
+
+    #[test]
+    fn loads_ron() {
+        let file = TempConfig::new(
+            "load.ron",
+            r#"(
+                adapter: Some("nvidia"),
+                power_preference: r#low-power,
+                color_format: hdr,
+                reversed_z: true,
+            )"#,
+        );
+        let config = RendererConfig::load(&file.0).unwrap();
+        assert_eq!(config.adapter.as_deref(), Some("nvidia"));
+        assert_eq!(config.power_preference, PowerPreferenceConfig::LowPower);
+        assert_eq!(config.color_format, ColorFormatConfig::Hdr);
+        assert!(config.reversed_z);
+        assert_eq!(config.sample_count, 4);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let toml = TempConfig::new("unknown.toml", "sample_counts = 4");
+        assert!(matches!(RendererConfig::load(&toml.0), Err(ConfigError::Toml { .. })));
+
+        let ron = TempConfig::new("unknown.ron", "(msaa: 4)");
+        assert!(matches!(RendererConfig::load(&ron.0), Err(ConfigError::Ron { .. })));
+    }
+
+    #[test]
+    fn rejects_unsupported_extension() {
+        let file = TempConfig::new("config.json", "{}");
+        assert!(matches!(RendererConfig::load(&file.0), Err(ConfigError::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn parses_config_names() {
+        assert_eq!("high-performance".parse::<PowerPreferenceConfig>().unwrap(), PowerPreferenceConfig::HighPerformance);
+        assert_eq!("low-power".parse::<PowerPreferenceConfig>().unwrap(), PowerPreferenceConfig::LowPower);
+        assert_eq!("dx12".parse::<Backend>().unwrap(), Backend::Dx12);
+        assert_eq!("immediate".parse::<PresentModeConfig>().unwrap(), PresentModeConfig::Immediate);
+        assert_eq!("hdr".parse::<ColorFormatConfig>().unwrap(), ColorFormatConfig::Hdr);
+
+        assert!(matches!("HighPerformance".parse::<PowerPreferenceConfig>(), Err(ConfigError::InvalidValue(_))));
+        assert!(matches!("opengl".parse::<Backend>(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_features() {
+        let config = RendererConfig {
+            features: vec!["POLYGON_MODE_LINE".into(), "TELEPORTATION".into()],
+            ..Default::default()
+        };
+        assert!(matches!(config.features(), Err(ConfigError::UnknownFeature(name)) if name == "TELEPORTATION"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validates_sample_count() {
+        for sample_count in [1, 2, 4, 8] {
+            let config = RendererConfig { sample_count, ..Default::default() };
+            assert!(config.validate().is_ok());
+        }
+        for sample_count in [0, 3, 16] {
+            let config = RendererConfig { sample_count, ..Default::default() };
+            assert!(matches!(config.validate(), Err(ConfigError::InvalidSampleCount(count)) if count == sample_count));
+        }
+
+        let file = TempConfig::new("samples.toml", "sample_count = 3");
+        assert!(matches!(RendererConfig::load(&file.0), Err(ConfigError::InvalidSampleCount(3))));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::render::camera::{Camera, Projection};
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector2, Vector3, Zero};
+use std::f32::consts::PI;
+use std::time::Duration;
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// Keeps the camera from flipping over when looking straight along the up axis.
+const MIN_POLAR_ANGLE: f32 = 0.01;
+/// Pixels of a touchpad scroll that count as one wheel notch.
+const PIXELS_PER_LINE: f32 = 50.0;
+
+/// Moves a [`Camera`] in response to window input.
+///
+/// Events are accumulated as they arrive and applied once per frame in `update`.
+pub trait CameraController {
+    fn window_event(&mut self, event: &WindowEvent);
+
+    fn device_event(&mut self, _event: &DeviceEvent) {}
+
+    fn update(&mut self, camera: &mut Camera, dt: Duration);
+}
+
+/// Rotates around the camera target with the left mouse button, pans with the right or
+/// middle button and zooms with the scroll wheel.
+pub struct OrbitController {
+    /// Radians per pixel of mouse movement.
+    pub rotate_speed: f32,
+    /// Fraction of the target distance per pixel of mouse movement.
+    pub pan_speed: f32,
+    /// Fraction of the target distance per scroll wheel notch.
+    pub zoom_speed: f32,
+
+    rotating: bool,
+    panning: bool,
+    rotate_delta: Vector2<f32>,
+    pan_delta: Vector2<f32>,
+    zoom_delta: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            rotate_speed: 0.005,
+            pan_speed: 0.001,
+            zoom_speed: 0.1,
+            rotating: false,
+            panning: false,
+            rotate_delta: Vector2::zero(),
+            pan_delta: Vector2::zero(),
+            zoom_delta: 0.0,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.rotating = pressed,
+                    MouseButton::Right | MouseButton::Middle => self.panning = pressed,
+                    _ => (),
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => self.zoom_delta += scroll_lines(delta),
+            WindowEvent::Focused(false) => {
+                self.rotating = false;
+                self.panning = false;
+            }
+            _ => (),
+        }
+    }
+
+    fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = *event {
+            let delta = Vector2::new(dx as f32, dy as f32);
+            if self.rotating {
+                self.rotate_delta += delta;
+            } else if self.panning {
+                self.pan_delta += delta;
+            }
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, _dt: Duration) {
+        let up = camera.up().normalize();
+        let target = camera.target();
+        let mut offset = camera.eye() - target;
+        let distance = offset.magnitude().max(1e-3);
+
+        let rotate = std::mem::replace(&mut self.rotate_delta, Vector2::zero()) * self.rotate_speed;
+        offset = rotate_polar(offset, up, -rotate.y);
+        offset = Quaternion::from_axis_angle(up, Rad(-rotate.x)).rotate_vector(offset);
+
+        let zoom = (1.0 + self.zoom_speed).powf(-std::mem::take(&mut self.zoom_delta));
+        offset *= zoom;
+        // Moving an orthographic camera closer doesn't change its size on screen.
+        match camera.projection_mut() {
+            Projection::Orthographic { height, .. } => *height *= zoom,
+            Projection::OrthographicOffCenter {
+                left,
+                right,
+                bottom,
+                top,
+                ..
+            } => {
+                for extent in [left, right, bottom, top] {
+                    *extent *= zoom;
+                }
+            }
+            _ => (),
+        }
+
+        let pan = std::mem::replace(&mut self.pan_delta, Vector2::zero()) * self.pan_speed * distance;
+        let (right, camera_up) = camera_axes(-offset, up);
+        let translation = right * -pan.x + camera_up * pan.y;
+
+        camera.look_at(target + translation + offset, target + translation);
+    }
+}
+
+/// First-person camera: WASD moves, Q and E move down and up, holding Shift moves faster
+/// and dragging with the left mouse button looks around.
+pub struct FlyController {
+    /// Units per second.
+    pub move_speed: f32,
+    /// Radians per pixel of mouse movement.
+    pub look_speed: f32,
+    /// Speed multiplier while Shift is held.
+    pub boost: f32,
+
+    looking: bool,
+    forward: bool,
+    backward: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    boosting: bool,
+    look_delta: Vector2<f32>,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            move_speed: 2.0,
+            look_speed: 0.003,
+            boost: 4.0,
+            looking: false,
+            forward: false,
+            backward: false,
+            left: false,
+            right: false,
+            up: false,
+            down: false,
+            boosting: false,
+            look_delta: Vector2::zero(),
+        }
+    }
+}
+
+impl FlyController {
+    pub fn new(move_speed: f32) -> Self {
+        Self {
+            move_speed,
+            ..Default::default()
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return;
+                };
+                match code {
+                    KeyCode::KeyW | KeyCode::ArrowUp => self.forward = pressed,
+                    KeyCode::KeyS | KeyCode::ArrowDown => self.backward = pressed,
+                    KeyCode::KeyA | KeyCode::ArrowLeft => self.left = pressed,
+                    KeyCode::KeyD | KeyCode::ArrowRight => self.right = pressed,
+                    KeyCode::KeyE | KeyCode::Space => self.up = pressed,
+                    KeyCode::KeyQ | KeyCode::ControlLeft => self.down = pressed,
+                    KeyCode::ShiftLeft | KeyCode::ShiftRight => self.boosting = pressed,
+                    _ => (),
+                }
+            }
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.looking = *state == ElementState::Pressed;
+            }
+            WindowEvent::Focused(false) => {
+                // Key releases are not delivered to an unfocused window.
+                *self = Self {
+                    move_speed: self.move_speed,
+                    look_speed: self.look_speed,
+                    boost: self.boost,
+                    ..Default::default()
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = *event
+            && self.looking
+        {
+            self.look_delta += Vector2::new(dx as f32, dy as f32);
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, dt: Duration) {
+        let up = camera.up().normalize();
+        let eye = camera.eye();
+        let view = camera.target() - eye;
+        let distance = view.magnitude().max(1e-3);
+
+        let look = std::mem::replace(&mut self.look_delta, Vector2::zero()) * self.look_speed;
+        let mut forward = rotate_polar(view.normalize(), up, look.y);
+        forward = Quaternion::from_axis_angle(up, Rad(-look.x)).rotate_vector(forward);
+
+        let (right, _) = camera_axes(forward, up);
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let mut direction = forward * axis(self.forward, self.backward)
+            + right * axis(self.right, self.left)
+            + up * axis(self.up, self.down);
+        if direction.magnitude2() > 0.0 {
+            direction = direction.normalize();
+        }
+
+        let speed = if self.boosting { self.move_speed * self.boost } else { self.move_speed };
+        let eye = eye + direction * speed * dt.as_secs_f32();
+        camera.look_at(eye, eye + forward * distance);
+    }
+}
+
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => *y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+    }
+}
+
+/// Rotates `vector` towards (negative `angle`) or away from (positive `angle`) `up`,
+/// stopping just short of the poles.
+fn rotate_polar(vector: Vector3<f32>, up: Vector3<f32>, angle: f32) -> Vector3<f32> {
+    let axis = up.cross(vector);
+    if axis.magnitude2() < f32::EPSILON {
+        return vector;
+    }
+    let current = vector.normalize().dot(up).clamp(-1.0, 1.0).acos();
+    let polar = (current + angle).clamp(MIN_POLAR_ANGLE, PI - MIN_POLAR_ANGLE);
+    Quaternion::from_axis_angle(axis.normalize(), Rad(polar - current)).rotate_vector(vector)
+}
+
+/// Right and up vectors of a camera looking along `forward`.
+fn camera_axes(forward: Vector3<f32>, up: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let right = forward.cross(up);
+    if right.magnitude2() < f32::EPSILON {
+        return (Vector3::unit_x(), up);
+    }
+    let right = right.normalize();
+    (right, right.cross(forward).normalize())
+}
@@ -0,0 +1,61 @@
+use wgpu::{
+    Device, Extent3d, Operations, RenderPassDepthStencilAttachment, StoreOp,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor,
+};
+
+pub struct DepthTexture {
+    pub view: TextureView,
+    pub format: TextureFormat,
+}
+
+impl DepthTexture {
+    pub const DEFAULT_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+    pub const CLEAR_DEPTH: f32 = 1.0;
+    pub const REVERSED_CLEAR_DEPTH: f32 = 0.0;
+
+    pub fn new(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        Self { view, format }
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.format.has_stencil_aspect()
+    }
+
+    pub fn attachment(&self, reversed_z: bool) -> RenderPassDepthStencilAttachment<'_> {
+        let clear_depth = if reversed_z { Self::REVERSED_CLEAR_DEPTH } else { Self::CLEAR_DEPTH };
+        RenderPassDepthStencilAttachment {
+            view: &self.view,
+            depth_ops: Some(Operations {
+                load: wgpu::LoadOp::Clear(clear_depth),
+                store: StoreOp::Store,
+            }),
+            stencil_ops: self.has_stencil().then_some(Operations {
+                load: wgpu::LoadOp::Clear(0),
+                store: StoreOp::Store,
+            }),
+        }
+    }
+}
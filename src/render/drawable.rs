@@ -1,10 +1,17 @@
 use cgmath::Matrix4;
 use crate::render::material::Material;
 use crate::render::mesh::Mesh;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Drawable {
-    pub mesh: Mesh,
-    pub material: Material,
-    pub model_matrix: Matrix4<f32>
+    pub mesh: Arc<Mesh>,
+    pub material: Arc<Material>,
+}
+
+/// A drawable queued for the current frame with its world matrix.
+pub(crate) struct DrawItem {
+    pub mesh: Arc<Mesh>,
+    pub material: Arc<Material>,
+    pub model_matrix: Matrix4<f32>,
 }
@@ -0,0 +1,467 @@
+use crate::errors::NimbusError;
+use crate::render::camera::Projection;
+use crate::render::drawable::Drawable;
+use crate::render::material::{
+    AlphaMode, BaseColorType, Emissive, Material, MaterialTexture, MaterialType,
+    MetallicRoughnessType, NormalTexture, OcclusionTexture, RawMaterial, TextureSource,
+};
+use crate::render::mesh::{Mesh, RawMesh, Vertex};
+use crate::render::scene::{NodeTransform, Scene, SceneNode};
+use crate::render::renderer::Renderer;
+use cgmath::{InnerSpace, Quaternion, Vector3};
+use gltf::image::Format;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use gltf::mesh::Mode;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+use wgpu::{AddressMode, Color, FilterMode, PrimitiveTopology, SamplerDescriptor, TextureFormat};
+
+pub struct GltfImporter<'a, 'window> {
+    renderer: &'a mut Renderer<'window>,
+    materials: HashMap<Option<usize>, Arc<Material>>,
+    textures: HashMap<(usize, usize, bool), MaterialTexture>,
+    meshes: HashMap<usize, Vec<Drawable>>,
+}
+
+impl<'a, 'window> GltfImporter<'a, 'window> {
+    pub fn new(renderer: &'a mut Renderer<'window>) -> Self {
+        Self {
+            renderer,
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            meshes: HashMap::new(),
+        }
+    }
+
+    pub fn import<P: AsRef<Path>>(&mut self, path: P) -> crate::Result<Scene> {
+        let (document, buffers, images) = gltf::import(path)?;
+        let mut scene = Scene::new();
+
+        let Some(gltf_scene) = document.default_scene().or_else(|| document.scenes().next()) else {
+            return Ok(scene);
+        };
+
+        for node in gltf_scene.nodes() {
+            let index = self.import_node(&mut scene, &node, &buffers, &images)?;
+            scene.root_nodes.push(index);
+        }
+
+        Ok(scene)
+    }
+
+    fn import_node(
+        &mut self,
+        scene: &mut Scene,
+        node: &gltf::Node,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> crate::Result<usize> {
+        let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+        let local_transform = NodeTransform {
+            translation: translation.into(),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: scale.into(),
+        };
+        let mut drawables = match node.mesh() {
+            Some(mesh) => self.import_mesh(&mesh, buffers, images)?,
+            None => vec![],
+        }
+        .into_iter();
+
+        let mut scene_node = SceneNode::new(node.name().map(str::to_owned), local_transform);
+        scene_node.drawable = drawables.next();
+        scene_node.camera = node.camera().map(|camera| Self::import_camera(&camera));
+        let has_camera = scene_node.camera.is_some();
+        let index = scene.add_node(scene_node);
+        if has_camera {
+            scene.cameras.push(index);
+        }
+
+        // A node only holds one drawable, so extra primitives hang off it as children.
+        for drawable in drawables {
+            let mut child_node = SceneNode::new(node.name().map(str::to_owned), NodeTransform::default());
+            child_node.drawable = Some(drawable);
+            let child = scene.add_node(child_node);
+            scene.add_child(index, child);
+        }
+
+        for child_node in node.children() {
+            let child = self.import_node(scene, &child_node, buffers, images)?;
+            scene.add_child(index, child);
+        }
+
+        Ok(index)
+    }
+
+    // Perspective cameras follow the viewport's aspect ratio, so glTF's aspectRatio is
+    // unused. Orthographic cameras keep their authored xmag and ymag.
+    fn import_camera(camera: &gltf::Camera) -> Projection {
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => Projection::Perspective {
+                fov_y: perspective.yfov().to_degrees(),
+                z_near: perspective.znear(),
+                z_far: perspective.zfar(),
+                reversed_z: false,
+            },
+            // xmag and ymag are half the width and height.
+            gltf::camera::Projection::Orthographic(orthographic) => Projection::OrthographicOffCenter {
+                left: -orthographic.xmag(),
+                right: orthographic.xmag(),
+                bottom: -orthographic.ymag(),
+                top: orthographic.ymag(),
+                z_near: orthographic.znear(),
+                z_far: orthographic.zfar(),
+                reversed_z: false,
+            },
+        }
+    }
+
+    fn import_mesh(
+        &mut self,
+        mesh: &RawMesh,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> crate::Result<Vec<Drawable>> {
+        // Meshes instanced by several nodes share their buffers.
+        if let Some(cached) = self.meshes.get(&mesh.index()) {
+            return Ok(cached.clone());
+        }
+
+        let label = mesh.name().unwrap_or("Unnamed Mesh").to_owned();
+        let mut drawables = vec![];
+
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                warn!("Skipping primitive of mesh {label} without positions");
+                continue;
+            };
+
+            let mut vertices: Vec<Vertex> = positions
+                .map(|position| Vertex {
+                    position,
+                    ..Default::default()
+                })
+                .collect();
+            let indices: Option<Vec<u32>> = reader.read_indices().map(|indices| indices.into_u32().collect());
+            let (topology, indices) = Self::topology(primitive.mode(), vertices.len(), indices);
+
+            match reader.read_normals() {
+                Some(normals) => {
+                    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                        vertex.normal = normal;
+                    }
+                }
+                None => Self::generate_normals(&mut vertices, topology, indices.as_deref()),
+            }
+
+            if let Some(tex_coords) = reader.read_tex_coords(0) {
+                for (vertex, tex_coord) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+                    vertex.tex_coord = tex_coord;
+                }
+            }
+
+            if let Some(tex_coords) = reader.read_tex_coords(1) {
+                for (vertex, tex_coord) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+                    vertex.tex_coord_1 = tex_coord;
+                }
+            }
+
+            let mut mesh = Mesh::new(&self.renderer.device, &label, &vertices, indices.as_deref());
+            mesh.topology = topology;
+            let material = match self.import_material(&primitive.material(), images) {
+                Ok(material) => material,
+                Err(err @ (NimbusError::ShaderCompileError { .. } | NimbusError::ShaderPreprocessError(_))) => {
+                    warn!("Skipping primitive of mesh {label}: {err}");
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            drawables.push(Drawable {
+                mesh: Arc::new(mesh),
+                material,
+            });
+        }
+
+        self.meshes.insert(mesh.index(), drawables.clone());
+        Ok(drawables)
+    }
+
+    /// Maps a glTF primitive mode to a wgpu topology. wgpu has no fans or line loops,
+    /// so those are rewritten as triangle lists and line strips.
+    fn topology(mode: Mode, vertex_count: usize, indices: Option<Vec<u32>>) -> (PrimitiveTopology, Option<Vec<u32>>) {
+        let indices_or_sequential = |indices: Option<Vec<u32>>| indices.unwrap_or_else(|| (0..vertex_count as u32).collect());
+        match mode {
+            Mode::Points => (PrimitiveTopology::PointList, indices),
+            Mode::Lines => (PrimitiveTopology::LineList, indices),
+            Mode::LineStrip => (PrimitiveTopology::LineStrip, indices),
+            Mode::LineLoop => {
+                let mut indices = indices_or_sequential(indices);
+                if let Some(&first) = indices.first() {
+                    indices.push(first);
+                }
+                (PrimitiveTopology::LineStrip, Some(indices))
+            }
+            Mode::Triangles => (PrimitiveTopology::TriangleList, indices),
+            Mode::TriangleStrip => (PrimitiveTopology::TriangleStrip, indices),
+            Mode::TriangleFan => {
+                let fan = indices_or_sequential(indices);
+                let list = (1..fan.len().saturating_sub(1))
+                    .flat_map(|i| [fan[0], fan[i], fan[i + 1]])
+                    .collect();
+                (PrimitiveTopology::TriangleList, Some(list))
+            }
+        }
+    }
+
+    fn generate_normals(vertices: &mut [Vertex], topology: PrimitiveTopology, indices: Option<&[u32]>) {
+        let count = indices.map_or(vertices.len(), <[u32]>::len);
+        let index = |i: usize| indices.map_or(i, |indices| indices[i] as usize);
+        let triangles: Vec<[usize; 3]> = match topology {
+            PrimitiveTopology::TriangleList => (0..count / 3)
+                .map(|t| [index(t * 3), index(t * 3 + 1), index(t * 3 + 2)])
+                .collect(),
+            // Every other strip triangle has its winding flipped.
+            PrimitiveTopology::TriangleStrip => (0..count.saturating_sub(2))
+                .map(|t| match t % 2 {
+                    0 => [index(t), index(t + 1), index(t + 2)],
+                    _ => [index(t + 1), index(t), index(t + 2)],
+                })
+                .collect(),
+            // Points and lines have no surface to derive a normal from.
+            _ => return,
+        };
+
+        let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); vertices.len()];
+        for [a, b, c] in triangles {
+            let pa = Vector3::from(vertices[a].position);
+            let pb = Vector3::from(vertices[b].position);
+            let pc = Vector3::from(vertices[c].position);
+            let face_normal = (pb - pa).cross(pc - pa);
+            normals[a] += face_normal;
+            normals[b] += face_normal;
+            normals[c] += face_normal;
+        }
+
+        for (vertex, normal) in vertices.iter_mut().zip(normals) {
+            if normal.magnitude2() > 0.0 {
+                vertex.normal = normal.normalize().into();
+            }
+        }
+    }
+
+    fn import_material(
+        &mut self,
+        material: &RawMaterial,
+        images: &[gltf::image::Data],
+    ) -> crate::Result<Arc<Material>> {
+        if let Some(cached) = self.materials.get(&material.index()) {
+            return Ok(cached.clone());
+        }
+
+        let name = material
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("Material {}", material.index().map_or(-1, |i| i as i64)));
+        let pbr = material.pbr_metallic_roughness();
+
+        let [r, g, b, a] = pbr.base_color_factor();
+        let color = Color {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+            a: a as f64,
+        };
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => {
+                let MaterialTexture { texture, texture_view, sampler, tex_coord, source } =
+                    self.import_texture(&info.texture(), info.tex_coord(), images, true);
+                BaseColorType::Texture { texture, texture_view, sampler, tex_coord, color, source }
+            }
+            None => BaseColorType::Factor { color },
+        };
+
+        let ty = if material.unlit() {
+            MaterialType::Unlit { base_color }
+        } else {
+            let metallic_roughness = match pbr.metallic_roughness_texture() {
+                Some(info) => {
+                    let MaterialTexture { texture, texture_view, sampler, tex_coord, source } =
+                        self.import_texture(&info.texture(), info.tex_coord(), images, false);
+                    MetallicRoughnessType::Texture {
+                        texture,
+                        texture_view,
+                        sampler,
+                        tex_coord,
+                        source,
+                        metallic: pbr.metallic_factor(),
+                        roughness: pbr.roughness_factor(),
+                    }
+                }
+                None => MetallicRoughnessType::Factor {
+                    metallic: Some(pbr.metallic_factor()),
+                    roughness: Some(pbr.roughness_factor()),
+                },
+            };
+
+            let normal = material.normal_texture().map(|info| NormalTexture {
+                texture: self.import_texture(&info.texture(), info.tex_coord(), images, false),
+                scale: info.scale(),
+            });
+            let occlusion = material.occlusion_texture().map(|info| OcclusionTexture {
+                texture: self.import_texture(&info.texture(), info.tex_coord(), images, false),
+                strength: info.strength(),
+            });
+            let emissive = Emissive {
+                factor: material.emissive_factor(),
+                texture: material
+                    .emissive_texture()
+                    .map(|info| self.import_texture(&info.texture(), info.tex_coord(), images, true)),
+            };
+
+            MaterialType::Pbr {
+                base_color,
+                metallic_roughness,
+                normal,
+                occlusion,
+                emissive,
+            }
+        };
+
+        let alpha_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+                cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            },
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        };
+        let shaders = self
+            .renderer
+            .material_shaders(&name, &ty, alpha_mode)?
+            .expect("glTF materials always map to a built-in shader");
+
+        let mut material_out = Material::new(
+            name,
+            shaders.vertex_shader,
+            shaders.fragment_shader,
+            ty,
+        );
+        material_out.alpha_mode = alpha_mode;
+        material_out.double_sided = material.double_sided();
+        material_out.shader_permutation = Some(shaders.permutation);
+
+        let material_out = Arc::new(material_out);
+        self.materials.insert(material.index(), material_out.clone());
+        Ok(material_out)
+    }
+
+    fn import_texture(
+        &mut self,
+        texture: &gltf::Texture,
+        tex_coord: u32,
+        images: &[gltf::image::Data],
+        srgb: bool,
+    ) -> MaterialTexture {
+        // Vertices only carry TEXCOORD_0 and TEXCOORD_1.
+        let tex_coord = if tex_coord > 1 {
+            warn!(
+                "Texture {} uses TEXCOORD_{tex_coord}, which is not supported; sampling TEXCOORD_0 instead",
+                texture.index()
+            );
+            0
+        } else {
+            tex_coord
+        };
+
+        let image_index = texture.source().index();
+        let sampler_index = texture.sampler().index().unwrap_or(usize::MAX);
+        let key = (image_index, sampler_index, srgb);
+        if let Some(cached) = self.textures.get(&key) {
+            return MaterialTexture {
+                tex_coord,
+                ..cached.clone()
+            };
+        }
+
+        let image = &images[image_index];
+        let label = texture.source().name().unwrap_or("Unnamed Texture");
+        let format = match srgb {
+            true => TextureFormat::Rgba8UnormSrgb,
+            false => TextureFormat::Rgba8Unorm,
+        };
+
+        let gltf_sampler = texture.sampler();
+        let source = TextureSource {
+            label: label.to_owned(),
+            width: image.width,
+            height: image.height,
+            format,
+            data: Self::to_rgba8(image).into(),
+            sampler: SamplerDescriptor {
+                address_mode_u: Self::address_mode(gltf_sampler.wrap_s()),
+                address_mode_v: Self::address_mode(gltf_sampler.wrap_t()),
+                mag_filter: match gltf_sampler.mag_filter() {
+                    Some(MagFilter::Nearest) => FilterMode::Nearest,
+                    _ => FilterMode::Linear,
+                },
+                min_filter: match gltf_sampler.min_filter() {
+                    Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest | MinFilter::NearestMipmapLinear) => {
+                        FilterMode::Nearest
+                    }
+                    _ => FilterMode::Linear,
+                },
+                ..Default::default()
+            },
+        };
+
+        let material_texture =
+            MaterialTexture::from_source(source, tex_coord, &self.renderer.device, &self.renderer.queue);
+        self.textures.insert(key, material_texture.clone());
+        material_texture
+    }
+
+    fn address_mode(mode: WrappingMode) -> AddressMode {
+        match mode {
+            WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => AddressMode::Repeat,
+        }
+    }
+
+    fn to_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+        let pixels = &image.pixels;
+        match image.format {
+            Format::R8 => pixels.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+            Format::R8G8 => pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+            Format::R8G8B8 => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            Format::R8G8B8A8 => pixels.clone(),
+            // 16 bit channels are little endian, keep the most significant byte.
+            Format::R16 => pixels.chunks_exact(2).flat_map(|p| [p[1], p[1], p[1], 255]).collect(),
+            Format::R16G16 => pixels.chunks_exact(4).flat_map(|p| [p[1], p[3], 0, 255]).collect(),
+            Format::R16G16B16 => pixels.chunks_exact(6).flat_map(|p| [p[1], p[3], p[5], 255]).collect(),
+            Format::R16G16B16A16 => pixels.chunks_exact(8).flat_map(|p| [p[1], p[3], p[5], p[7]]).collect(),
+            Format::R32G32B32FLOAT => pixels
+                .chunks_exact(12)
+                .flat_map(|p| {
+                    let channel = |i: usize| Self::float_to_u8(&p[i * 4..i * 4 + 4]);
+                    [channel(0), channel(1), channel(2), 255]
+                })
+                .collect(),
+            Format::R32G32B32A32FLOAT => pixels
+                .chunks_exact(16)
+                .flat_map(|p| {
+                    let channel = |i: usize| Self::float_to_u8(&p[i * 4..i * 4 + 4]);
+                    [channel(0), channel(1), channel(2), channel(3)]
+                })
+                .collect(),
+        }
+    }
+
+    fn float_to_u8(bytes: &[u8]) -> u8 {
+        let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
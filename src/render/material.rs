@@ -1,105 +1,577 @@
+use crate::errors::NimbusError;
+use crate::render::preprocessor::ShaderDefines;
+use crate::render::renderer::Renderer;
+use crate::render::shader::ShaderPermutation;
+use bytemuck::{bytes_of, Pod, Zeroable};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::warn;
+use wgpu::util::{DeviceExt, TextureDataOrder};
 use wgpu::{
-    BindGroupLayoutEntry, BindingType, Color, PushConstantRange, Sampler, SamplerBindingType,
-    ShaderModule, ShaderStages, Texture, TextureSampleType, TextureView, TextureViewDimension,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry,
+    BindingResource, BindingType, BlendState, Buffer, Color, Device, ErrorFilter, Extent3d, Face, PushConstantRange,
+    Queue, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderStages, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
 };
 
-#[derive(Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialId(u64);
+
+impl MaterialId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A cloned material gets a fresh [`MaterialId`], so edits to the clone never reuse the
+/// original's cached bind group. Share a material between drawables through an `Arc`.
 pub struct Material {
+    id: MaterialId,
     pub name: String,
     pub vertex_shader: ShaderModule,
     pub fragment_shader: ShaderModule,
     pub ty: MaterialType,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    /// Set for materials whose shaders come from the shader library; the renderer
+    /// then draws with the library's current module for this permutation.
+    pub(crate) shader_permutation: Option<ShaderPermutation>,
+}
+
+impl Clone for Material {
+    fn clone(&self) -> Self {
+        Self {
+            id: MaterialId::next(),
+            name: self.name.clone(),
+            vertex_shader: self.vertex_shader.clone(),
+            fragment_shader: self.fragment_shader.clone(),
+            ty: self.ty.clone(),
+            alpha_mode: self.alpha_mode,
+            double_sided: self.double_sided,
+            shader_permutation: self.shader_permutation.clone(),
+        }
+    }
 }
 
 impl Material {
+    pub fn new(
+        name: impl Into<String>,
+        vertex_shader: ShaderModule,
+        fragment_shader: ShaderModule,
+        ty: MaterialType,
+    ) -> Self {
+        Self {
+            id: MaterialId::next(),
+            name: name.into(),
+            vertex_shader,
+            fragment_shader,
+            ty,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+            shader_permutation: None,
+        }
+    }
+
+    pub fn id(&self) -> MaterialId {
+        self.id
+    }
+
+    /// Re-uploads textures from their retained sources and recompiles library shaders
+    /// after the renderer recreated its device. Custom material resources, textures
+    /// without a source and shaders from outside the library belong to the application
+    /// and are left untouched.
+    pub(crate) fn recreate(&mut self, renderer: &mut Renderer, uploads: &mut TextureUploads) -> crate::Result<()> {
+        if let MaterialType::Custom(_) = self.ty {
+            warn!("Material {} has custom resources that must be recreated by the application", self.name);
+            return Ok(());
+        }
+
+        for slot in self.ty.recreate_textures(uploads, &renderer.device, &renderer.queue) {
+            warn!(
+                "Material {} has no source for its {} texture; it must be recreated by the application",
+                self.name,
+                slot.name()
+            );
+        }
+
+        match &self.shader_permutation {
+            Some(permutation) => {
+                let module = renderer.shader_module(&self.name, permutation)?;
+                self.vertex_shader = module.clone();
+                self.fragment_shader = module;
+            }
+            None => warn!(
+                "Material {} uses shaders from outside the shader library that must be recreated by the application",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
     pub fn is_transparent(&self) -> bool {
         match &self.ty {
-            MaterialType::Unlit { base_color } => match base_color {
-                BaseColorType::Factor { color } => color.a < 1.0,
-                BaseColorType::Texture { .. } => true,
+            MaterialType::Custom(custom) => custom.transparent,
+            _ => self.alpha_mode == AlphaMode::Blend,
+        }
+    }
+
+    pub fn blend_state(&self) -> Option<BlendState> {
+        if let MaterialType::Custom(custom) = &self.ty {
+            return custom.blend;
+        }
+
+        match self.is_transparent() {
+            true => Some(BlendState::ALPHA_BLENDING),
+            false => Some(BlendState::REPLACE),
+        }
+    }
+
+    pub fn cull_mode(&self) -> Option<Face> {
+        match self.double_sided {
+            true => None,
+            false => Some(Face::Back),
+        }
+    }
+
+    pub fn push_constant_data(&self) -> Vec<u8> {
+        match &self.ty {
+            MaterialType::Custom(custom) => custom.push_constant_data.clone(),
+            _ => bytes_of(&self.factors()).to_vec(),
+        }
+    }
+
+    pub fn factors(&self) -> MaterialFactors {
+        let mut factors = MaterialFactors {
+            alpha_cutoff: match self.alpha_mode {
+                AlphaMode::Mask { cutoff } => cutoff,
+                _ => 0.0,
             },
-            MaterialType::Pbr { base_color, .. } => match base_color {
-                BaseColorType::Factor { color } => color.a < 1.0,
-                BaseColorType::Texture { .. } => true,
+            alpha_mode: match self.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask { .. } => 1,
+                AlphaMode::Blend => 2,
             },
-            MaterialType::Custom => {
-                unimplemented!()
+            ..Default::default()
+        };
+
+        let base_color = match &self.ty {
+            MaterialType::Pbr { base_color, .. } | MaterialType::Unlit { base_color } => base_color,
+            MaterialType::Custom(_) => return factors,
+        };
+        let color = match base_color {
+            BaseColorType::Factor { color } | BaseColorType::Texture { color, .. } => color,
+        };
+        factors.base_color = [color.r as f32, color.g as f32, color.b as f32, color.a as f32];
+
+        if let MaterialType::Pbr {
+            metallic_roughness,
+            normal,
+            occlusion,
+            emissive,
+            ..
+        } = &self.ty
+        {
+            (factors.metallic, factors.roughness) = match metallic_roughness {
+                MetallicRoughnessType::Factor { metallic, roughness } => {
+                    (metallic.unwrap_or(1.0), roughness.unwrap_or(1.0))
+                }
+                MetallicRoughnessType::Texture { metallic, roughness, .. } => (*metallic, *roughness),
+            };
+            factors.normal_scale = normal.as_ref().map_or(1.0, |normal| normal.scale);
+            factors.occlusion_strength = occlusion.as_ref().map_or(1.0, |occlusion| occlusion.strength);
+            factors.emissive = emissive.factor;
+        }
+
+        for (slot, tex_coord) in self.ty.tex_coords() {
+            if tex_coord == 1 {
+                factors.tex_coord_sets |= 1 << slot as u32;
             }
         }
+
+        factors
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask { cutoff: f32 },
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn shader_define(&self) -> Option<&'static str> {
+        match self {
+            AlphaMode::Mask { .. } => Some("ALPHA_MASK"),
+            AlphaMode::Opaque | AlphaMode::Blend => None,
+        }
     }
 }
 
+/// Per-material factors pushed to the fragment stage of the built-in materials.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub alpha_cutoff: f32,
+    /// Bit `slot` is set when that texture slot samples `TEXCOORD_1`.
+    pub tex_coord_sets: u32,
+    pub alpha_mode: u32,
+    pub _padding: [u32; 2],
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum MaterialType {
     Pbr {
         base_color: BaseColorType,
         metallic_roughness: MetallicRoughnessType,
+        normal: Option<NormalTexture>,
+        occlusion: Option<OcclusionTexture>,
+        emissive: Emissive,
     },
     Unlit {
         base_color: BaseColorType,
     },
-    Custom,
+    Custom(CustomMaterial),
 }
 
 impl MaterialType {
-    pub fn bind_group_layout_entries(&self) -> Vec<BindGroupLayoutEntry> {
-        let mut entries = vec![];
+    pub fn texture_bindings(&self) -> Vec<(TextureSlot, &TextureView, &Sampler)> {
+        let mut bindings = vec![];
 
         match self {
             MaterialType::Pbr {
                 base_color,
                 metallic_roughness,
+                normal,
+                occlusion,
+                emissive,
             } => {
-                if let BaseColorType::Texture { .. } = base_color {
-                    Self::push_entry(&mut entries, 0);
+                if let BaseColorType::Texture {
+                    texture_view,
+                    sampler,
+                    ..
+                } = base_color
+                {
+                    bindings.push((TextureSlot::BaseColor, texture_view, sampler));
                 }
 
-                if let MetallicRoughnessType::Texture { .. } = metallic_roughness {
-                    Self::push_entry(&mut entries, 3)
+                if let MetallicRoughnessType::Texture {
+                    texture_view,
+                    sampler,
+                    ..
+                } = metallic_roughness
+                {
+                    bindings.push((TextureSlot::MetallicRoughness, texture_view, sampler));
+                }
+
+                if let Some(NormalTexture { texture, .. }) = normal {
+                    bindings.push((TextureSlot::Normal, &texture.texture_view, &texture.sampler));
+                }
+
+                if let Some(OcclusionTexture { texture, .. }) = occlusion {
+                    bindings.push((TextureSlot::Occlusion, &texture.texture_view, &texture.sampler));
+                }
+
+                if let Some(texture) = &emissive.texture {
+                    bindings.push((TextureSlot::Emissive, &texture.texture_view, &texture.sampler));
                 }
             }
 
             MaterialType::Unlit { base_color } => {
-                if let BaseColorType::Texture { .. } = base_color {
-                    Self::push_entry(&mut entries, 0);
+                if let BaseColorType::Texture {
+                    texture_view,
+                    sampler,
+                    ..
+                } = base_color
+                {
+                    bindings.push((TextureSlot::BaseColor, texture_view, sampler));
                 }
             }
 
-            MaterialType::Custom => {
-                unimplemented!()
+            MaterialType::Custom(_) => {}
+        }
+
+        bindings
+    }
+
+    /// Re-uploads every texture from its retained source and returns the slots whose
+    /// texture has no source to upload from.
+    pub(crate) fn recreate_textures(
+        &mut self,
+        uploads: &mut TextureUploads,
+        device: &Device,
+        queue: &Queue,
+    ) -> Vec<TextureSlot> {
+        let mut missing = vec![];
+        let base_color = match self {
+            MaterialType::Pbr {
+                base_color,
+                metallic_roughness,
+                normal,
+                occlusion,
+                emissive,
+            } => {
+                if let MetallicRoughnessType::Texture {
+                    texture,
+                    texture_view,
+                    sampler,
+                    source,
+                    ..
+                } = metallic_roughness
+                {
+                    match source {
+                        Some(source) => (*texture, *texture_view, *sampler) = uploads.upload(source, device, queue),
+                        None => missing.push(TextureSlot::MetallicRoughness),
+                    }
+                }
+                let textures = [
+                    (TextureSlot::Normal, normal.as_mut().map(|normal| &mut normal.texture)),
+                    (TextureSlot::Occlusion, occlusion.as_mut().map(|occlusion| &mut occlusion.texture)),
+                    (TextureSlot::Emissive, emissive.texture.as_mut()),
+                ];
+                for (slot, texture) in textures {
+                    if let Some(texture) = texture
+                        && !texture.recreate(uploads, device, queue)
+                    {
+                        missing.push(slot);
+                    }
+                }
+                base_color
+            }
+            MaterialType::Unlit { base_color } => base_color,
+            MaterialType::Custom(_) => return missing,
+        };
+
+        if let BaseColorType::Texture {
+            texture,
+            texture_view,
+            sampler,
+            source,
+            ..
+        } = base_color
+        {
+            match source {
+                Some(source) => (*texture, *texture_view, *sampler) = uploads.upload(source, device, queue),
+                None => missing.push(TextureSlot::BaseColor),
             }
         }
+        missing
+    }
+
+    pub fn tex_coords(&self) -> Vec<(TextureSlot, u32)> {
+        let mut tex_coords = vec![];
 
-        entries
+        match self {
+            MaterialType::Pbr {
+                base_color,
+                metallic_roughness,
+                normal,
+                occlusion,
+                emissive,
+            } => {
+                if let BaseColorType::Texture { tex_coord, .. } = base_color {
+                    tex_coords.push((TextureSlot::BaseColor, *tex_coord));
+                }
+                if let MetallicRoughnessType::Texture { tex_coord, .. } = metallic_roughness {
+                    tex_coords.push((TextureSlot::MetallicRoughness, *tex_coord));
+                }
+                if let Some(normal) = normal {
+                    tex_coords.push((TextureSlot::Normal, normal.texture.tex_coord));
+                }
+                if let Some(occlusion) = occlusion {
+                    tex_coords.push((TextureSlot::Occlusion, occlusion.texture.tex_coord));
+                }
+                if let Some(texture) = &emissive.texture {
+                    tex_coords.push((TextureSlot::Emissive, texture.tex_coord));
+                }
+            }
+            MaterialType::Unlit { base_color } => {
+                if let BaseColorType::Texture { tex_coord, .. } = base_color {
+                    tex_coords.push((TextureSlot::BaseColor, *tex_coord));
+                }
+            }
+            MaterialType::Custom(_) => {}
+        }
+
+        tex_coords
+    }
+
+    pub fn texture_slots(&self) -> Vec<TextureSlot> {
+        self.texture_bindings()
+            .into_iter()
+            .map(|(slot, ..)| slot)
+            .collect()
+    }
+
+    pub fn bind_group_layout_entries(&self) -> Vec<BindGroupLayoutEntry> {
+        match self {
+            MaterialType::Custom(custom) => custom.bind_group_layout_entries.clone(),
+            _ => self
+                .texture_slots()
+                .into_iter()
+                .flat_map(TextureSlot::layout_entries)
+                .collect(),
+        }
+    }
+
+    pub fn shader_defines(&self) -> ShaderDefines {
+        let mut defines = ShaderDefines::new();
+        for slot in TextureSlot::ALL {
+            slot.add_defines(&mut defines);
+        }
+        for slot in self.texture_slots() {
+            defines.insert(format!("HAS_{}_TEXTURE", slot.define_prefix()), String::new());
+        }
+        defines
     }
 
     pub fn push_constant_ranges(&self) -> Vec<PushConstantRange> {
         match self {
             MaterialType::Pbr { .. } | MaterialType::Unlit { .. } => vec![PushConstantRange {
                 stages: ShaderStages::FRAGMENT,
-                range: 0..64,
+                range: 0..size_of::<MaterialFactors>() as u32,
             }],
-            MaterialType::Custom => vec![],
+            MaterialType::Custom(custom) => custom.push_constant_ranges.clone(),
         }
     }
 
-    fn push_entry(entries: &mut Vec<BindGroupLayoutEntry>, binding: u32) {
-        entries.push(BindGroupLayoutEntry {
-            binding,
-            visibility: ShaderStages::FRAGMENT,
-            ty: BindingType::Texture {
-                sample_type: TextureSampleType::Float { filterable: true },
-                view_dimension: TextureViewDimension::D2,
-                multisampled: true,
+    pub fn bind_group_entries(&self) -> Vec<BindGroupEntry<'_>> {
+        match self {
+            MaterialType::Custom(custom) => custom
+                .resources
+                .iter()
+                .map(CustomResource::bind_group_entry)
+                .collect(),
+            _ => self
+                .texture_bindings()
+                .into_iter()
+                .flat_map(|(slot, texture_view, sampler)| {
+                    [
+                        BindGroupEntry {
+                            binding: slot.texture_binding(),
+                            resource: BindingResource::TextureView(texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: slot.sampler_binding(),
+                            resource: BindingResource::Sampler(sampler),
+                        },
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Texture slots of the built-in materials. Each slot has a fixed texture
+/// binding at `2 * slot` followed by its sampler.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    BaseColor,
+    MetallicRoughness,
+    Normal,
+    Occlusion,
+    Emissive,
+}
+
+impl TextureSlot {
+    pub const ALL: [TextureSlot; 5] = [
+        TextureSlot::BaseColor,
+        TextureSlot::MetallicRoughness,
+        TextureSlot::Normal,
+        TextureSlot::Occlusion,
+        TextureSlot::Emissive,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextureSlot::BaseColor => "base_color",
+            TextureSlot::MetallicRoughness => "metallic_roughness",
+            TextureSlot::Normal => "normal",
+            TextureSlot::Occlusion => "occlusion",
+            TextureSlot::Emissive => "emissive",
+        }
+    }
+
+    pub fn texture_binding(self) -> u32 {
+        self as u32 * 2
+    }
+
+    pub fn sampler_binding(self) -> u32 {
+        self.texture_binding() + 1
+    }
+
+    pub fn layout_entries(self) -> [BindGroupLayoutEntry; 2] {
+        [
+            BindGroupLayoutEntry {
+                binding: self.texture_binding(),
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
             },
-            count: None,
-        });
-        entries.push(BindGroupLayoutEntry {
-            binding: binding + 1,
-            visibility: ShaderStages::FRAGMENT,
-            ty: BindingType::Sampler(SamplerBindingType::Filtering),
-            count: None,
-        });
+            BindGroupLayoutEntry {
+                binding: self.sampler_binding(),
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    /// WGSL expression returned by `sample_<name>` when the material has no texture
+    /// in this slot.
+    pub fn fallback(self) -> &'static str {
+        match self {
+            TextureSlot::Normal => "vec4<f32>(0.5, 0.5, 1.0, 1.0)",
+            _ => "vec4<f32>(1.0)",
+        }
+    }
+
+    /// Texture and sampler declarations plus a `sample_<name>` function, guarded by
+    /// `HAS_<NAME>_TEXTURE` so that textureless materials sample the fallback.
+    pub fn wgsl(self) -> String {
+        let name = self.name();
+        let prefix = self.define_prefix();
+        let fallback = self.fallback();
+        format!(
+            "#ifdef HAS_{prefix}_TEXTURE
+@group(0) @binding({prefix}_TEXTURE_BINDING) var {name}_texture: texture_2d<f32>;
+@group(0) @binding({prefix}_SAMPLER_BINDING) var {name}_sampler: sampler;
+
+fn sample_{name}(in: VertexOutput) -> vec4<f32> {{
+    return textureSample({name}_texture, {name}_sampler, material_uv({prefix}_SLOT, in));
+}}
+#else
+fn sample_{name}(in: VertexOutput) -> vec4<f32> {{
+    return {fallback};
+}}
+#endif
+"
+        )
+    }
+
+    pub fn define_prefix(self) -> String {
+        self.name().to_uppercase()
+    }
+
+    pub fn add_defines(self, defines: &mut ShaderDefines) {
+        let prefix = self.define_prefix();
+        defines.insert(format!("{prefix}_SLOT"), format!("{}u", self as u32));
+        defines.insert(format!("{prefix}_TEXTURE_BINDING"), self.texture_binding().to_string());
+        defines.insert(format!("{prefix}_SAMPLER_BINDING"), self.sampler_binding().to_string());
     }
 }
 
@@ -112,6 +584,9 @@ pub enum BaseColorType {
         texture: Texture,
         texture_view: TextureView,
         sampler: Sampler,
+        tex_coord: u32,
+        color: Color,
+        source: Option<Arc<TextureSource>>,
     },
 }
 
@@ -121,6 +596,10 @@ pub enum MetallicRoughnessType {
         texture: Texture,
         texture_view: TextureView,
         sampler: Sampler,
+        tex_coord: u32,
+        metallic: f32,
+        roughness: f32,
+        source: Option<Arc<TextureSource>>,
     },
     Factor {
         metallic: Option<f32>,
@@ -128,4 +607,227 @@ pub enum MetallicRoughnessType {
     },
 }
 
+#[derive(Clone)]
+pub struct MaterialTexture {
+    pub texture: Texture,
+    pub texture_view: TextureView,
+    pub sampler: Sampler,
+    pub tex_coord: u32,
+    pub source: Option<Arc<TextureSource>>,
+}
+
+impl MaterialTexture {
+    pub fn from_source(source: TextureSource, tex_coord: u32, device: &Device, queue: &Queue) -> Self {
+        let (texture, texture_view, sampler) = source.upload(device, queue);
+        Self {
+            texture,
+            texture_view,
+            sampler,
+            tex_coord,
+            source: Some(Arc::new(source)),
+        }
+    }
+
+    /// Re-uploads the texture from its source. Returns `false` if it has none.
+    pub(crate) fn recreate(&mut self, uploads: &mut TextureUploads, device: &Device, queue: &Queue) -> bool {
+        let Some(source) = &self.source else {
+            return false;
+        };
+        (self.texture, self.texture_view, self.sampler) = uploads.upload(source, device, queue);
+        true
+    }
+}
+
+/// Textures uploaded during one device recovery, keyed by their shared source so a
+/// texture used by several materials is uploaded once. The map holds on to each source
+/// so its address can't be reused by another one while the pass runs.
+#[derive(Default)]
+pub(crate) struct TextureUploads {
+    uploads: HashMap<*const TextureSource, (Arc<TextureSource>, Texture, TextureView, Sampler)>,
+}
+
+impl TextureUploads {
+    fn upload(&mut self, source: &Arc<TextureSource>, device: &Device, queue: &Queue) -> (Texture, TextureView, Sampler) {
+        let (_, texture, texture_view, sampler) = self.uploads.entry(Arc::as_ptr(source)).or_insert_with(|| {
+            let (texture, texture_view, sampler) = source.upload(device, queue);
+            (source.clone(), texture, texture_view, sampler)
+        });
+        (texture.clone(), texture_view.clone(), sampler.clone())
+    }
+}
+
+/// CPU copy of a single-mip 2D texture, kept so it can be uploaded again after device loss.
+#[derive(Clone, Debug)]
+pub struct TextureSource {
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub data: Arc<[u8]>,
+    pub sampler: SamplerDescriptor<'static>,
+}
+
+impl TextureSource {
+    pub fn upload(&self, device: &Device, queue: &Queue) -> (Texture, TextureView, Sampler) {
+        let texture = device.create_texture_with_data(
+            queue,
+            &TextureDescriptor {
+                label: Some(&self.label),
+                size: Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: self.format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            &self.data,
+        );
+        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some(&format!("{} Sampler", self.label)),
+            ..self.sampler.clone()
+        });
+        (texture, texture_view, sampler)
+    }
+}
+
+#[derive(Clone)]
+pub struct NormalTexture {
+    pub texture: MaterialTexture,
+    pub scale: f32,
+}
+
+#[derive(Clone)]
+pub struct OcclusionTexture {
+    pub texture: MaterialTexture,
+    pub strength: f32,
+}
+
+#[derive(Clone, Default)]
+pub struct Emissive {
+    pub factor: [f32; 3],
+    pub texture: Option<MaterialTexture>,
+}
+
+#[derive(Clone, Default)]
+pub struct CustomMaterial {
+    pub bind_group_layout_entries: Vec<BindGroupLayoutEntry>,
+    pub resources: Vec<CustomResource>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
+    pub push_constant_data: Vec<u8>,
+    pub blend: Option<BlendState>,
+    pub transparent: bool,
+}
+
+#[derive(Clone)]
+pub enum CustomResource {
+    Uniform {
+        binding: u32,
+        buffer: Buffer,
+    },
+    Texture {
+        binding: u32,
+        texture_view: TextureView,
+    },
+    Sampler {
+        binding: u32,
+        sampler: Sampler,
+    },
+}
+
+impl CustomResource {
+    fn bind_group_entry(&self) -> BindGroupEntry<'_> {
+        match self {
+            CustomResource::Uniform { binding, buffer } => BindGroupEntry {
+                binding: *binding,
+                resource: buffer.as_entire_binding(),
+            },
+            CustomResource::Texture {
+                binding,
+                texture_view,
+            } => BindGroupEntry {
+                binding: *binding,
+                resource: BindingResource::TextureView(texture_view),
+            },
+            CustomResource::Sampler { binding, sampler } => BindGroupEntry {
+                binding: *binding,
+                resource: BindingResource::Sampler(sampler),
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CachedBindGroup {
+    material: Weak<Material>,
+    // `None` once creation failed validation.
+    bind_group: Option<BindGroup>,
+}
+
+/// Bind groups keyed by material. Entries only live as long as their material: `prune`
+/// drops the ones whose material was dropped, and a material edited in place through
+/// `Arc::make_mut` gets a new bind group.
+#[derive(Default, Clone)]
+pub(crate) struct MaterialBindGroupCache {
+    bind_groups: HashMap<MaterialId, CachedBindGroup>,
+}
+
+impl MaterialBindGroupCache {
+    /// Returns the bind group of `material`, creating it on first use. Like pipelines, a
+    /// material whose bind group failed validation returns the error once and `Ok(None)`
+    /// afterwards.
+    pub fn get_or_create(
+        &mut self,
+        material: &Arc<Material>,
+        layout: &BindGroupLayout,
+        device: &Device,
+    ) -> crate::Result<Option<&BindGroup>> {
+        let current = self
+            .bind_groups
+            .get(&material.id)
+            .is_some_and(|cached| std::ptr::eq(cached.material.as_ptr(), Arc::as_ptr(material)));
+
+        if !current {
+            device.push_error_scope(ErrorFilter::Validation);
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some(&format!("{} Bind Group", material.name)),
+                layout,
+                entries: &material.ty.bind_group_entries(),
+            });
+            let error = pollster::block_on(device.pop_error_scope());
+            self.bind_groups.insert(
+                material.id,
+                CachedBindGroup {
+                    material: Arc::downgrade(material),
+                    bind_group: error.is_none().then_some(bind_group),
+                },
+            );
+            if let Some(err) = error {
+                return Err(NimbusError::BindGroupCreationError {
+                    material: material.name.clone(),
+                    message: err.to_string(),
+                });
+            }
+        }
+        Ok(self.bind_groups.get(&material.id).and_then(|cached| cached.bind_group.as_ref()))
+    }
+
+    /// Drops the bind groups of materials that no longer exist.
+    pub fn prune(&mut self) -> usize {
+        let before = self.bind_groups.len();
+        self.bind_groups.retain(|_, cached| cached.material.strong_count() > 0);
+        before - self.bind_groups.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.bind_groups.clear();
+    }
+}
+
 pub type RawMaterial<'a> = gltf::Material<'a>;
@@ -1,18 +1,74 @@
-use wgpu::{Buffer, BufferAddress, VertexAttribute, VertexBufferLayout, VertexStepMode};
+use bytemuck::{cast_slice, Pod, Zeroable};
+use std::sync::Arc;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{vertex_attr_array, Buffer, BufferAddress, BufferUsages, Device, IndexFormat, PrimitiveTopology, VertexAttribute, VertexBufferLayout, VertexStepMode};
 
 #[derive(Clone)]
 pub struct Mesh {
     pub vertex_buffer: Buffer,
     pub index_buffer: Option<Buffer>,
+    pub index_format: IndexFormat,
     pub index_count: u32,
     pub vertex_count: u32,
+    pub topology: PrimitiveTopology,
 
     pub vertex_attributes: Vec<VertexAttribute>,
     pub array_stride: BufferAddress,
+
+    // Kept on the CPU so the buffers can be rebuilt after device loss.
+    pub label: String,
+    pub vertices: Arc<[Vertex]>,
+    pub indices: Option<Arc<[u32]>>,
 }
 
 impl Mesh {
-    pub fn vertex_buffer_layout(&self) -> VertexBufferLayout {
+    pub fn new(device: &Device, label: &str, vertices: &[Vertex], indices: Option<&[u32]>) -> Self {
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, label, vertices, indices);
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_format: IndexFormat::Uint32,
+            index_count: indices.map_or(0, |indices| indices.len() as u32),
+            vertex_count: vertices.len() as u32,
+            topology: PrimitiveTopology::TriangleList,
+            vertex_attributes: Vertex::ATTRIBUTES.to_vec(),
+            array_stride: size_of::<Vertex>() as BufferAddress,
+            label: label.to_owned(),
+            vertices: vertices.into(),
+            indices: indices.map(Into::into),
+        }
+    }
+
+    pub fn recreate(&mut self, device: &Device) {
+        (self.vertex_buffer, self.index_buffer) =
+            Self::create_buffers(device, &self.label, &self.vertices, self.indices.as_deref());
+    }
+
+    fn create_buffers(
+        device: &Device,
+        label: &str,
+        vertices: &[Vertex],
+        indices: Option<&[u32]>,
+    ) -> (Buffer, Option<Buffer>) {
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&format!("{label} Vertex Buffer")),
+            contents: cast_slice(vertices),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let index_buffer = indices.map(|indices| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(&format!("{label} Index Buffer")),
+                contents: cast_slice(indices),
+                usage: BufferUsages::INDEX,
+            })
+        });
+
+        (vertex_buffer, index_buffer)
+    }
+
+    pub fn vertex_buffer_layout(&self) -> VertexBufferLayout<'_> {
         VertexBufferLayout {
             array_stride: self.array_stride,
             step_mode: VertexStepMode::Vertex,
@@ -21,4 +77,22 @@ impl Mesh {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coord: [f32; 2],
+    pub tex_coord_1: [f32; 2],
+}
+
+impl Vertex {
+    pub const ATTRIBUTES: [VertexAttribute; 4] = vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+        3 => Float32x2
+    ];
+}
+
 pub type RawMesh<'a> = gltf::Mesh<'a>;
@@ -1,7 +1,15 @@
-pub mod renderer;
-pub mod drawable;
-pub mod mesh;
-pub mod material;
-pub mod scene;
-pub mod pipeline;
-mod camera;
\ No newline at end of file
+pub(crate) mod renderer;
+pub(crate) mod drawable;
+pub(crate) mod mesh;
+pub(crate) mod material;
+pub(crate) mod scene;
+pub(crate) mod pipeline;
+pub(crate) mod importer;
+pub(crate) mod object;
+pub(crate) mod depth;
+pub(crate) mod shader;
+pub(crate) mod preprocessor;
+pub(crate) mod config;
+pub(crate) mod camera;
+#[cfg(feature = "windowing")]
+pub(crate) mod controller;
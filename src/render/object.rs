@@ -0,0 +1,127 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
+use cgmath::{Matrix, Matrix4, SquareMatrix};
+use std::num::NonZeroU64;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBinding,
+    BufferBindingType, BufferDescriptor, BufferUsages, Device, Queue, ShaderStages,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub struct ObjectUniform {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 4]; 4],
+}
+
+impl ObjectUniform {
+    pub fn new(model_matrix: Matrix4<f32>) -> Self {
+        let normal_matrix = model_matrix
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(Matrix4::identity());
+        Self {
+            model: model_matrix.into(),
+            normal: normal_matrix.into(),
+        }
+    }
+}
+
+pub struct ObjectBuffer {
+    pub bind_group_layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+    buffer: Buffer,
+    stride: BufferAddress,
+    capacity: usize,
+    staging: Vec<u8>,
+}
+
+impl ObjectBuffer {
+    const INITIAL_CAPACITY: usize = 64;
+
+    pub fn new(device: &Device) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as BufferAddress;
+        let stride = (size_of::<ObjectUniform>() as BufferAddress).div_ceil(alignment) * alignment;
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Object Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(size_of::<ObjectUniform>() as u64),
+                },
+                count: None,
+            }],
+        });
+
+        let (buffer, bind_group) =
+            Self::create_buffer(device, &bind_group_layout, stride, Self::INITIAL_CAPACITY);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            buffer,
+            stride,
+            capacity: Self::INITIAL_CAPACITY,
+            staging: vec![],
+        }
+    }
+
+    fn create_buffer(
+        device: &Device,
+        layout: &BindGroupLayout,
+        stride: BufferAddress,
+        capacity: usize,
+    ) -> (Buffer, BindGroup) {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Object Buffer"),
+            size: stride * capacity as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Object Bind Group"),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(size_of::<ObjectUniform>() as u64),
+                }),
+            }],
+        });
+
+        (buffer, bind_group)
+    }
+
+    pub fn write(&mut self, device: &Device, queue: &Queue, objects: impl ExactSizeIterator<Item = ObjectUniform>) {
+        let count = objects.len();
+        if count == 0 {
+            return;
+        }
+
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            (self.buffer, self.bind_group) =
+                Self::create_buffer(device, &self.bind_group_layout, self.stride, self.capacity);
+        }
+
+        self.staging.clear();
+        self.staging.resize(self.stride as usize * count, 0);
+        for (index, object) in objects.enumerate() {
+            let offset = index * self.stride as usize;
+            self.staging[offset..offset + size_of::<ObjectUniform>()].copy_from_slice(bytes_of(&object));
+        }
+
+        queue.write_buffer(&self.buffer, 0, &self.staging);
+    }
+
+    pub fn offset(&self, index: usize) -> u32 {
+        (index as BufferAddress * self.stride) as u32
+    }
+}
@@ -1,105 +1,312 @@
-use crate::render::drawable::Drawable;
-use crate::render::material::{BaseColorType, MaterialType, MetallicRoughnessType};
-use std::collections::HashMap;
+use crate::render::material::Material;
+use crate::render::mesh::Mesh;
+use crate::errors::NimbusError;
+use crate::render::shader::{compile_shader, ShaderLibrary, ShaderPermutation};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tracing::{error, info};
 use wgpu::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindingResource, BlendState, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
-    DepthStencilState, Device, Face, FragmentState, FrontFace, MultisampleState,
-    PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
-    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, StencilState,
-    SurfaceConfiguration, TextureFormat, VertexState,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BlendState, BufferAddress, ColorTargetState,
+    ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device, ErrorFilter, Face, FragmentState,
+    FrontFace, IndexFormat, MultisampleState, PipelineCompilationOptions,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, PushConstantRange,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState, TextureFormat,
+    VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
 };
 
 #[derive(Clone)]
-pub struct Pipeline {
+pub(crate) struct Pipeline {
     pub render_pipeline: RenderPipeline,
     pub material_bind_group_layout: BindGroupLayout,
-    pub pipeline_layout: PipelineLayout,
-    pub material_bind_group: BindGroup
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    pub material_layout: Vec<BindGroupLayoutEntry>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
+    pub vertex_shader: ShaderModule,
+    pub fragment_shader: ShaderModule,
+    pub array_stride: BufferAddress,
+    pub vertex_attributes: Vec<VertexAttribute>,
+    pub topology: PrimitiveTopology,
+    pub strip_index_format: Option<IndexFormat>,
+    pub cull_mode: Option<Face>,
+    pub blend: Option<BlendState>,
+    pub depth_write_enabled: bool,
+    pub depth_compare: CompareFunction,
+    pub color_format: TextureFormat,
+    pub depth_format: TextureFormat,
+    pub sample_count: u32,
+}
+
+impl PipelineKey {
+    pub fn new(
+        mesh: &Mesh,
+        material: &Material,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        sample_count: u32,
+        reversed_z: bool,
+    ) -> Self {
+        Self {
+            material_layout: material.ty.bind_group_layout_entries(),
+            push_constant_ranges: material.ty.push_constant_ranges(),
+            vertex_shader: material.vertex_shader.clone(),
+            fragment_shader: material.fragment_shader.clone(),
+            array_stride: mesh.array_stride,
+            vertex_attributes: mesh.vertex_attributes.clone(),
+            topology: mesh.topology,
+            strip_index_format: mesh.topology.is_strip().then_some(mesh.index_format),
+            cull_mode: material.cull_mode(),
+            blend: material.blend_state(),
+            depth_write_enabled: !material.is_transparent(),
+            depth_compare: if reversed_z { CompareFunction::Greater } else { CompareFunction::Less },
+            color_format,
+            depth_format,
+            sample_count,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub pipelines_created: u64,
+    pub shaders_compiled: u64,
+    pub shaders_reloaded: u64,
+}
+
+#[derive(Clone)]
+struct CachedShader {
+    module: ShaderModule,
+    dependencies: BTreeSet<String>,
 }
 
 #[derive(Default, Clone)]
-pub struct PipelineCache {
-    pipelines: HashMap<String, Pipeline>,
+pub(crate) struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Pipeline>,
+    failed_pipelines: HashSet<PipelineKey>,
+    bind_group_layouts: HashMap<Vec<BindGroupLayoutEntry>, BindGroupLayout>,
+    shader_modules: HashMap<ShaderPermutation, CachedShader>,
+    stats: PipelineCacheStats,
 }
 
 impl PipelineCache {
+    /// Returns the pipeline for `key`, creating it on first use. A key whose pipeline
+    /// failed validation returns the error once and `Ok(None)` afterwards, so callers
+    /// can skip the drawable without retrying every frame.
     pub fn get_or_create(
         &mut self,
-        drawable: &Drawable,
+        key: &PipelineKey,
+        material: &Material,
+        device: &Device,
+        frame_bind_group_layouts: &[&BindGroupLayout],
+    ) -> crate::Result<Option<&Pipeline>> {
+        if self.failed_pipelines.contains(key) {
+            return Ok(None);
+        }
+
+        if self.pipelines.contains_key(key) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let material_bind_group_layout = self.bind_group_layout(&key.material_layout, device).clone();
+            let pipeline = Self::create_render_pipeline(
+                key,
+                material,
+                device,
+                material_bind_group_layout,
+                frame_bind_group_layouts,
+            );
+            match pipeline {
+                Ok(pipeline) => {
+                    self.stats.pipelines_created += 1;
+                    self.pipelines.insert(key.clone(), pipeline);
+                }
+                Err(err) => {
+                    self.failed_pipelines.insert(key.clone());
+                    return Err(err);
+                }
+            }
+        }
+        Ok(self.pipelines.get(key))
+    }
+
+    pub fn get_or_create_shader(
+        &mut self,
+        material: &str,
+        permutation: &ShaderPermutation,
+        library: &ShaderLibrary,
         device: &Device,
-        config: &SurfaceConfiguration,
-    ) -> &Pipeline {
-        self.pipelines
-            .entry(drawable.material.name.clone())
-            .or_insert_with(|| Self::create_render_pipeline(drawable, device, config))
+    ) -> crate::Result<ShaderModule> {
+        if let Some(cached) = self.shader_modules.get(permutation) {
+            return Ok(cached.module.clone());
+        }
+
+        let source = library.preprocess(permutation)?;
+        let module = compile_shader(device, material, permutation, &source)?;
+        self.stats.shaders_compiled += 1;
+        self.shader_modules.insert(
+            permutation.clone(),
+            CachedShader {
+                module: module.clone(),
+                dependencies: source.dependencies,
+            },
+        );
+        Ok(module)
+    }
+
+    /// Recompiles every cached permutation that depends on one of the `changed`
+    /// sources. Permutations that fail to preprocess or compile keep their previous
+    /// module; the error is logged. Pipelines using a replaced module are dropped and
+    /// rebuilt the next time they are drawn.
+    pub fn reload_shaders(&mut self, changed: &[String], library: &ShaderLibrary, device: &Device) -> usize {
+        self.reload_shaders_where(library, device, |dependencies| {
+            changed.iter().any(|name| dependencies.contains(name))
+        })
+    }
+
+    pub fn reload_all_shaders(&mut self, library: &ShaderLibrary, device: &Device) -> usize {
+        self.reload_shaders_where(library, device, |_| true)
+    }
+
+    fn reload_shaders_where(
+        &mut self,
+        library: &ShaderLibrary,
+        device: &Device,
+        mut predicate: impl FnMut(&BTreeSet<String>) -> bool,
+    ) -> usize {
+        let mut replaced = vec![];
+
+        for (permutation, cached) in &mut self.shader_modules {
+            if !predicate(&cached.dependencies) {
+                continue;
+            }
+
+            let label = permutation.label();
+            let source = match library.preprocess(permutation) {
+                Ok(source) => source,
+                Err(err) => {
+                    error!("Failed to reload shader {label}: {err}");
+                    continue;
+                }
+            };
+
+            let module = match compile_shader(device, &permutation.source, permutation, &source) {
+                Ok(module) => module,
+                Err(err) => {
+                    error!("Failed to reload shader: {err}");
+                    continue;
+                }
+            };
+
+            info!("Reloaded shader {label}");
+            self.stats.shaders_reloaded += 1;
+            cached.dependencies = source.dependencies;
+            replaced.push(std::mem::replace(&mut cached.module, module));
+        }
+
+        for old in &replaced {
+            self.invalidate_shader(old);
+        }
+        replaced.len()
+    }
+
+    pub fn bind_group_layout(&mut self, entries: &[BindGroupLayoutEntry], device: &Device) -> &BindGroupLayout {
+        self.bind_group_layouts
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("Material Bind Group Layout"),
+                    entries,
+                })
+            })
+    }
+
+    pub fn stats(&self) -> PipelineCacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = PipelineCacheStats::default();
+    }
+
+    pub fn invalidate_where(&mut self, mut predicate: impl FnMut(&PipelineKey) -> bool) -> usize {
+        self.failed_pipelines.retain(|key| !predicate(key));
+        let before = self.pipelines.len();
+        self.pipelines.retain(|key, _| !predicate(key));
+        before - self.pipelines.len()
+    }
+
+    pub fn invalidate_shader(&mut self, shader: &ShaderModule) -> usize {
+        self.invalidate_where(|key| &key.vertex_shader == shader || &key.fragment_shader == shader)
+    }
+
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+        self.failed_pipelines.clear();
+        self.bind_group_layouts.clear();
     }
 
     fn create_render_pipeline(
-        drawable: &Drawable,
+        key: &PipelineKey,
+        material: &Material,
         device: &Device,
-        config: &SurfaceConfiguration,
-    ) -> Pipeline {
-        let material_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &drawable.material.ty.bind_group_layout_entries(),
-            label: Some(&format!(
-                "{} Material Bind Group Layout",
-                drawable.material.name.clone()
-            )),
-        });
+        material_bind_group_layout: BindGroupLayout,
+        frame_bind_group_layouts: &[&BindGroupLayout],
+    ) -> crate::Result<Pipeline> {
+        device.push_error_scope(ErrorFilter::Validation);
+
+        let mut bind_group_layouts = vec![&material_bind_group_layout];
+        bind_group_layouts.extend_from_slice(frame_bind_group_layouts);
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some(&format!(
-                "{} Render Pipeline Layout",
-                drawable.material.name.clone()
-            )),
-            bind_group_layouts: &[&material_bind_group_layout],
-            push_constant_ranges: &drawable.material.ty.push_constant_ranges(),
+            label: Some(&format!("{} Render Pipeline Layout", material.name)),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &key.push_constant_ranges,
         });
 
         let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some(&format!(
-                "{} Render Pipeline",
-                drawable.material.name.clone()
-            )),
+            label: Some(&format!("{} Render Pipeline", material.name)),
             layout: Some(&pipeline_layout),
             vertex: VertexState {
-                module: &drawable.material.vertex_shader,
+                module: &key.vertex_shader,
                 entry_point: Some("vertexMain"),
-                buffers: &[drawable.mesh.vertex_buffer_layout()],
+                buffers: &[VertexBufferLayout {
+                    array_stride: key.array_stride,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &key.vertex_attributes,
+                }],
                 compilation_options: PipelineCompilationOptions::default(),
             },
             fragment: Some(FragmentState {
-                module: &drawable.material.fragment_shader,
+                module: &key.fragment_shader,
                 entry_point: Some("fragmentMain"),
                 targets: &[Some(ColorTargetState {
-                    format: config.format,
-                    blend: match drawable.material.is_transparent() {
-                        true => Some(BlendState::ALPHA_BLENDING),
-                        false => Some(BlendState::REPLACE),
-                    },
+                    format: key.color_format,
+                    blend: key.blend,
                     write_mask: ColorWrites::ALL,
                 })],
                 compilation_options: PipelineCompilationOptions::default(),
             }),
             primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
+                topology: key.topology,
+                strip_index_format: key.strip_index_format,
                 front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
+                cull_mode: key.cull_mode,
                 unclipped_depth: false,
                 polygon_mode: PolygonMode::Fill,
                 conservative: false,
             },
             depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
+                format: key.depth_format,
+                depth_write_enabled: key.depth_write_enabled,
+                depth_compare: key.depth_compare,
                 stencil: StencilState::default(),
                 bias: DepthBiasState::default(),
             }),
             multisample: MultisampleState {
-                count: 4,
+                count: key.sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -107,80 +314,16 @@ impl PipelineCache {
             cache: None,
         });
 
-        let mut entries = vec![];
-
-        match &drawable.material.ty {
-            MaterialType::Pbr {
-                base_color,
-                metallic_roughness,
-            } => {
-                if let BaseColorType::Texture {
-                    texture_view,
-                    sampler,
-                    ..
-                } = base_color
-                {
-                    entries.push(BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(texture_view),
-                    });
-                    entries.push(BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::Sampler(sampler),
-                    });
-                }
-
-                if let MetallicRoughnessType::Texture {
-                    texture_view,
-                    sampler,
-                    ..
-                } = metallic_roughness
-                {
-                    entries.push(BindGroupEntry {
-                        binding: 2,
-                        resource: BindingResource::TextureView(texture_view),
-                    });
-                    entries.push(BindGroupEntry {
-                        binding: 3,
-                        resource: BindingResource::Sampler(sampler),
-                    });
-                }
-            }
-
-            MaterialType::Unlit { base_color } => {
-                if let BaseColorType::Texture {
-                    texture_view,
-                    sampler,
-                    ..
-                } = base_color
-                {
-                    entries.push(BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(texture_view),
-                    });
-                    entries.push(BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::Sampler(sampler),
-                    });
-                }
-            }
-
-            MaterialType::Custom => {
-                unimplemented!()
-            }
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            return Err(NimbusError::PipelineCreationError {
+                material: material.name.clone(),
+                message: err.to_string(),
+            });
         }
 
-        let material_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some(&format!("{} Bind Group", drawable.material.name.clone())),
-            layout: &material_bind_group_layout,
-            entries: &entries,
-        });
-
-        Pipeline {
+        Ok(Pipeline {
             material_bind_group_layout,
-            pipeline_layout,
             render_pipeline,
-            material_bind_group,
-        }
+        })
     }
 }
@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+pub(crate) type ShaderDefines = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PreprocessedSource {
+    pub code: String,
+    pub dependencies: BTreeSet<String>,
+    /// Original file and line of every line in `code`.
+    pub line_map: Vec<SourceLocation>,
+}
+
+impl PreprocessedSource {
+    /// Maps a 1-based line of the preprocessed output back to its source file.
+    pub fn location(&self, line: usize) -> Option<&SourceLocation> {
+        self.line_map.get(line.checked_sub(1)?)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PreprocessError {
+    #[error("{file}:{line}: unknown include \"{name}\"")]
+    UnknownInclude {
+        file: String,
+        line: usize,
+        name: String,
+    },
+
+    #[error("{file}:{line}: malformed #{directive}")]
+    MalformedDirective {
+        file: String,
+        line: usize,
+        directive: String,
+    },
+
+    #[error("{file}:{line}: #{directive} without a matching #ifdef")]
+    UnmatchedDirective {
+        file: String,
+        line: usize,
+        directive: String,
+    },
+
+    #[error("{file}:{line}: #ifdef is never closed")]
+    UnterminatedConditional { file: String, line: usize },
+}
+
+struct Conditional {
+    line: usize,
+    parent_active: bool,
+    taken: bool,
+    in_else: bool,
+}
+
+/// A small C-style preprocessor for WGSL supporting `#include "name"`,
+/// `#define NAME [value]`, `#undef`, `#ifdef`, `#ifndef`, `#else` and `#endif`.
+/// Includes resolve against the registered sources and are only expanded once per output.
+#[derive(Default, Clone)]
+pub(crate) struct ShaderPreprocessor {
+    sources: HashMap<String, String>,
+}
+
+impl ShaderPreprocessor {
+    pub fn add_source(&mut self, name: impl Into<String>, source: impl Into<String>) {
+        self.sources.insert(name.into(), source.into());
+    }
+
+    pub fn process(&self, name: &str, defines: &ShaderDefines) -> Result<PreprocessedSource, PreprocessError> {
+        let source = self.resolve(name).ok_or_else(|| PreprocessError::UnknownInclude {
+            file: name.to_owned(),
+            line: 0,
+            name: name.to_owned(),
+        })?;
+
+        let mut defines = defines.clone();
+        let mut included = HashSet::from([name.to_owned()]);
+        let mut output = PreprocessedSource {
+            code: String::with_capacity(source.len()),
+            ..Default::default()
+        };
+        self.process_source(name, &source, &mut defines, &mut included, &mut output)?;
+        output.dependencies = included.into_iter().collect();
+        Ok(output)
+    }
+
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.sources.get(name).cloned()
+    }
+
+    fn process_source(
+        &self,
+        file: &str,
+        source: &str,
+        defines: &mut ShaderDefines,
+        included: &mut HashSet<String>,
+        output: &mut PreprocessedSource,
+    ) -> Result<(), PreprocessError> {
+        let mut conditionals: Vec<Conditional> = vec![];
+
+        for (index, raw_line) in source.lines().enumerate() {
+            let line = index + 1;
+            let active = conditionals.last().is_none_or(|c| c.parent_active && c.taken != c.in_else);
+            let trimmed = raw_line.trim_start();
+
+            let Some(directive_line) = trimmed.strip_prefix('#') else {
+                if active {
+                    output.code.push_str(&Self::substitute(raw_line, defines));
+                    output.code.push('\n');
+                    output.line_map.push(SourceLocation {
+                        file: file.to_owned(),
+                        line,
+                    });
+                }
+                continue;
+            };
+
+            let mut parts = directive_line.split_whitespace();
+            let directive = parts.next().unwrap_or_default();
+            let malformed = || PreprocessError::MalformedDirective {
+                file: file.to_owned(),
+                line,
+                directive: directive.to_owned(),
+            };
+            let unmatched = || PreprocessError::UnmatchedDirective {
+                file: file.to_owned(),
+                line,
+                directive: directive.to_owned(),
+            };
+
+            match directive {
+                "ifdef" | "ifndef" => {
+                    let name = parts.next().ok_or_else(malformed)?;
+                    let defined = defines.contains_key(name);
+                    conditionals.push(Conditional {
+                        line,
+                        parent_active: active,
+                        taken: defined == (directive == "ifdef"),
+                        in_else: false,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals.last_mut().ok_or_else(unmatched)?;
+                    if conditional.in_else {
+                        return Err(unmatched());
+                    }
+                    conditional.in_else = true;
+                }
+                "endif" => {
+                    conditionals.pop().ok_or_else(unmatched)?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let name = parts.next().ok_or_else(malformed)?;
+                    let value = parts.collect::<Vec<_>>().join(" ");
+                    defines.insert(name.to_owned(), value);
+                }
+                "undef" => {
+                    let name = parts.next().ok_or_else(malformed)?;
+                    defines.remove(name);
+                }
+                "include" => {
+                    let name = parts
+                        .next()
+                        .and_then(|name| name.strip_prefix('"')?.strip_suffix('"'))
+                        .ok_or_else(malformed)?;
+                    if !included.insert(name.to_owned()) {
+                        continue;
+                    }
+                    let included_source = self.resolve(name).ok_or_else(|| PreprocessError::UnknownInclude {
+                        file: file.to_owned(),
+                        line,
+                        name: name.to_owned(),
+                    })?;
+                    self.process_source(name, &included_source, defines, included, output)?;
+                }
+                _ => return Err(malformed()),
+            }
+        }
+
+        match conditionals.pop() {
+            Some(conditional) => Err(PreprocessError::UnterminatedConditional {
+                file: file.to_owned(),
+                line: conditional.line,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn substitute(line: &str, defines: &ShaderDefines) -> String {
+        let mut output = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            match defines.get(word) {
+                Some(value) if !value.is_empty() && !word.starts_with(|c: char| c.is_ascii_digit()) => {
+                    output.push_str(value)
+                }
+                _ => output.push_str(word),
+            }
+            rest = &rest[end..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor(sources: &[(&str, &str)]) -> ShaderPreprocessor {
+        let mut preprocessor = ShaderPreprocessor::default();
+        for (name, source) in sources {
+            preprocessor.add_source(*name, *source);
+        }
+        preprocessor
+    }
+
+    fn process(sources: &[(&str, &str)], defines: &[&str]) -> Result<PreprocessedSource, PreprocessError> {
+        let defines = defines.iter().map(|name| (name.to_string(), String::new())).collect();
+        preprocessor(sources).process(sources[0].0, &defines)
+    }
+
+    fn lines(output: &PreprocessedSource) -> Vec<&str> {
+        output.code.lines().collect()
+    }
+
+    #[test]
+    fn nested_conditionals_inside_inactive_parent_stay_inactive() {
+        let source = "\
+#ifdef OUTER
+#ifdef INNER
+a
+#else
+b
+#endif
+#else
+c
+#endif";
+        let output = process(&[("main.wgsl", source)], &["INNER"]).unwrap();
+        assert_eq!(lines(&output), ["c"]);
+
+        let output = process(&[("main.wgsl", source)], &["OUTER"]).unwrap();
+        assert_eq!(lines(&output), ["b"]);
+
+        let output = process(&[("main.wgsl", source)], &["OUTER", "INNER"]).unwrap();
+        assert_eq!(lines(&output), ["a"]);
+    }
+
+    #[test]
+    fn ifndef_and_undef() {
+        let source = "\
+#define FOO
+#undef FOO
+#ifndef FOO
+a
+#endif";
+        let output = process(&[("main.wgsl", source)], &[]).unwrap();
+        assert_eq!(lines(&output), ["a"]);
+    }
+
+    #[test]
+    fn defines_with_values_are_substituted_on_whole_words() {
+        let source = "\
+#define COUNT 4u
+#define OFFSET vec2<f32>(0.5, 0.5)
+let a = COUNT + COUNTER + 2COUNT;
+let b = OFFSET;";
+        let output = process(&[("main.wgsl", source)], &[]).unwrap();
+        assert_eq!(
+            lines(&output),
+            ["let a = 4u + COUNTER + 2COUNT;", "let b = vec2<f32>(0.5, 0.5);"]
+        );
+    }
+
+    #[test]
+    fn defines_inside_inactive_blocks_are_ignored() {
+        let source = "\
+#ifdef MISSING
+#define VALUE 1
+#endif
+VALUE";
+        let output = process(&[("main.wgsl", source)], &[]).unwrap();
+        assert_eq!(lines(&output), ["VALUE"]);
+    }
+
+    #[test]
+    fn includes_are_expanded_once() {
+        let output = process(
+            &[
+                ("main.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain"),
+                ("a.wgsl", "#include \"common.wgsl\"\na"),
+                ("b.wgsl", "#include \"common.wgsl\"\nb"),
+                ("common.wgsl", "common"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&output), ["common", "a", "b", "main"]);
+        assert_eq!(
+            output.dependencies,
+            BTreeSet::from(["main.wgsl", "a.wgsl", "b.wgsl", "common.wgsl"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn include_cycles_terminate() {
+        let output = process(
+            &[
+                ("a.wgsl", "#include \"b.wgsl\"\na"),
+                ("b.wgsl", "#include \"a.wgsl\"\nb"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&output), ["b", "a"]);
+    }
+
+    #[test]
+    fn unknown_include_is_an_error() {
+        let err = process(&[("main.wgsl", "\n#include \"missing.wgsl\"")], &[]).unwrap_err();
+        assert!(matches!(
+            err,
+            PreprocessError::UnknownInclude { file, line: 2, name } if file == "main.wgsl" && name == "missing.wgsl"
+        ));
+    }
+
+    #[test]
+    fn unmatched_directives_are_errors() {
+        for (source, expected) in [("#endif", "endif"), ("#else", "else"), ("#ifdef A\n#else\n#else\n#endif", "else")] {
+            let err = process(&[("main.wgsl", source)], &[]).unwrap_err();
+            assert!(
+                matches!(&err, PreprocessError::UnmatchedDirective { directive, .. } if directive == expected),
+                "{source:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn unterminated_conditional_reports_its_opening_line() {
+        let err = process(&[("main.wgsl", "a\n#ifdef A\n#ifdef B\n#endif")], &[]).unwrap_err();
+        assert!(matches!(err, PreprocessError::UnterminatedConditional { line: 2, .. }));
+    }
+
+    #[test]
+    fn malformed_directives_are_errors() {
+        for source in ["#ifdef", "#include missing.wgsl", "#pragma once"] {
+            let err = process(&[("main.wgsl", source)], &[]).unwrap_err();
+            assert!(matches!(err, PreprocessError::MalformedDirective { .. }), "{source:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn line_map_points_at_original_files() {
+        let output = process(
+            &[
+                ("main.wgsl", "#ifdef A\nskipped\n#endif\n#include \"common.wgsl\"\nmain"),
+                ("common.wgsl", "// common\ncommon"),
+            ],
+            &[],
+        )
+        .unwrap();
+        let location = |line| output.location(line).map(|location| location.to_string());
+        assert_eq!(location(0), None);
+        assert_eq!(location(1).as_deref(), Some("common.wgsl:1"));
+        assert_eq!(location(2).as_deref(), Some("common.wgsl:2"));
+        assert_eq!(location(3).as_deref(), Some("main.wgsl:5"));
+        assert_eq!(location(4), None);
+    }
+}
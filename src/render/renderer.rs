@@ -1,133 +1,518 @@
-use crate::render::camera::{Camera, CameraUniform};
-use crate::render::drawable::Drawable;
-use crate::render::pipeline::PipelineCache;
+use crate::errors::NimbusError;
+use crate::render::camera::{Camera, ViewUniform};
+use crate::render::config::{ColorFormatConfig, RendererConfig};
+use crate::render::depth::DepthTexture;
+use crate::render::drawable::{DrawItem, Drawable};
+use crate::render::material::MaterialBindGroupCache;
+use crate::render::object::{ObjectBuffer, ObjectUniform};
+use crate::render::pipeline::{PipelineCache, PipelineCacheStats, PipelineKey};
+use crate::render::material::{AlphaMode, MaterialType};
+use crate::render::shader::{MaterialShaders, ShaderLibrary, ShaderPermutation};
 use bytemuck::cast_slice;
+use cgmath::Matrix4;
+use std::io;
 use std::num::NonZeroU64;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::time::Instant;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::wgt::{CommandEncoderDescriptor, TextureViewDescriptor};
-use wgpu::{Adapter, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, Color, CommandEncoder, Device, IndexFormat, Instance, InstanceDescriptor, LoadOp, Operations, PowerPreference, Queue, RenderPassColorAttachment, RenderPassDescriptor, RequestAdapterOptions, ShaderStages, StoreOp, Surface, SurfaceConfiguration, SurfaceTexture, TextureUsages, TextureView};
+use wgpu::{Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Color, CommandEncoder, CompositeAlphaMode, Device, DeviceLostReason, Extent3d, Instance, InstanceDescriptor, LoadOp, MapMode, Operations, Origin3d, PollType, PresentMode, Queue, RenderPassColorAttachment, RenderPassDescriptor, RequestAdapterOptions, ShaderModule, ShaderStages, StoreOp, Surface, SurfaceConfiguration, SurfaceTarget, SurfaceTexture, TexelCopyBufferInfo, TexelCopyBufferLayout, TexelCopyTextureInfo, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, COPY_BYTES_PER_ROW_ALIGNMENT};
+use tracing::{error, info, warn};
+#[cfg(feature = "windowing")]
 use winit::dpi::PhysicalSize;
+#[cfg(feature = "windowing")]
 use winit::window::Window;
 
 pub struct Renderer<'window> {
     pub instance: Instance,
-    pub surface: Surface<'window>,
+    pub surface: Option<Surface<'window>>,
     pub surface_config: SurfaceConfiguration,
     pub adapter: Adapter,
     pub device: Device,
     pub queue: Queue,
-    pub pipeline_cache: PipelineCache,
-    pub render_queue: Vec<Drawable>,
-    
-    camera_uniform: CameraUniform,
-    camera_buffer: Buffer,
-    camera_bind_group_layout: BindGroupLayout,
-    camera_bind_group: BindGroup
+    pub(crate) pipeline_cache: PipelineCache,
+    pub(crate) material_bind_groups: MaterialBindGroupCache,
+    pub(crate) shader_library: ShaderLibrary,
+    pub(crate) render_queue: Vec<DrawItem>,
+    pub clear_color: Color,
+
+    offscreen_texture: Option<Texture>,
+    depth_texture: DepthTexture,
+    sample_count: u32,
+    msaa_view: Option<TextureView>,
+
+    start_time: Instant,
+    view_uniform: ViewUniform,
+    view_buffer: Buffer,
+    view_bind_group_layout: BindGroupLayout,
+    view_bind_group: BindGroup,
+
+    object_buffer: ObjectBuffer,
+    device_lost: Arc<AtomicBool>,
+    config: RendererConfig,
+    capture_requested: bool,
+    pending_capture: Option<Readback>,
+}
+
+struct Readback {
+    buffer: Buffer,
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+    unpadded_bytes_per_row: u32,
 }
 
 impl<'window> Renderer<'window> {
-    pub async fn new(window: Arc<Window>) -> crate::Result<Renderer<'window>> {
+    #[cfg(feature = "windowing")]
+    pub async fn new(window: Arc<Window>, config: RendererConfig) -> crate::Result<Renderer<'window>> {
         let PhysicalSize { width, height } = window.inner_size();
+        Self::with_surface(window, width, height, config).await
+    }
 
-        let instance = Instance::new(&InstanceDescriptor {
-            backends: Backends::PRIMARY,
-            ..Default::default()
-        });
-
-        let surface = instance.create_surface(window.clone())?;
-
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await?;
+    /// Creates a renderer presenting to any window handle wgpu can create a surface for.
+    pub async fn with_surface(
+        target: impl Into<SurfaceTarget<'window>>,
+        width: u32,
+        height: u32,
+        config: RendererConfig,
+    ) -> crate::Result<Renderer<'window>> {
+        let instance = Self::create_instance(&config);
+        let surface = instance.create_surface(target)?;
+        let adapter = Self::select_adapter(&instance, &config, Some(&surface)).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = match config.color_format.select(&surface_caps.formats) {
+            Some(format) => format,
+            None => {
+                warn!("{:?} output is not supported by the surface, falling back", config.color_format);
+                ColorFormatConfig::Sdr
+                    .select(&surface_caps.formats)
+                    .unwrap_or(surface_caps.formats[0])
+            }
+        };
 
         let surface_config = SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
+            // Copying out of the surface is only needed for screenshots, but costs nothing to allow.
+            usage: TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & TextureUsages::COPY_SRC),
             format: surface_format,
             width,
             height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: config.present_mode.select(&surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
-            desired_maximum_frame_latency: 2,
+            desired_maximum_frame_latency: config.frame_latency,
         };
 
+        Self::from_adapter(instance, Some(surface), surface_config, adapter, config).await
+    }
+
+    pub async fn new_headless(width: u32, height: u32, config: RendererConfig) -> crate::Result<Renderer<'static>> {
+        let instance = Self::create_instance(&config);
+        let adapter = Self::select_adapter(&instance, &config, None).await?;
+
+        let surface_config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            format: match config.color_format {
+                ColorFormatConfig::Sdr => TextureFormat::Rgba8UnormSrgb,
+                ColorFormatConfig::Hdr => TextureFormat::Rgba16Float,
+            },
+            width,
+            height,
+            present_mode: PresentMode::Fifo,
+            alpha_mode: CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: config.frame_latency,
+        };
+
+        let mut renderer = Renderer::from_adapter(instance, None, surface_config, adapter, config).await?;
+        renderer.offscreen_texture = Some(renderer.create_offscreen_texture());
+        Ok(renderer)
+    }
+
+    fn create_instance(config: &RendererConfig) -> Instance {
+        Instance::new(&InstanceDescriptor {
+            backends: config.backends(),
+            flags: config.instance_flags(),
+            ..Default::default()
+        })
+    }
+
+    async fn select_adapter(
+        instance: &Instance,
+        config: &RendererConfig,
+        surface: Option<&Surface<'_>>,
+    ) -> crate::Result<Adapter> {
+        if let Some(name) = &config.adapter {
+            let name = name.to_lowercase();
+            return instance
+                .enumerate_adapters(config.backends())
+                .into_iter()
+                .find(|adapter| {
+                    adapter.get_info().name.to_lowercase().contains(&name)
+                        && surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+                })
+                .ok_or_else(|| NimbusError::AdapterNotFound(name));
+        }
+
+        Ok(instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: config.power_preference.into(),
+                compatible_surface: surface,
+                force_fallback_adapter: config.force_fallback_adapter,
+            })
+            .await?)
+    }
+
+    async fn from_adapter(
+        instance: Instance,
+        surface: Option<Surface<'window>>,
+        surface_config: SurfaceConfiguration,
+        adapter: Adapter,
+        config: RendererConfig,
+    ) -> crate::Result<Renderer<'window>> {
+        config.validate()?;
+        let (device, queue, device_lost) = Self::request_device(&adapter, &config).await?;
+        let view_uniform = ViewUniform::new();
+        let (view_buffer, view_bind_group_layout, view_bind_group) = Self::create_view_resources(&device, &view_uniform);
+
+        let object_buffer = ObjectBuffer::new(&device);
+        let depth_texture = DepthTexture::new(
+            &device,
+            surface_config.width,
+            surface_config.height,
+            DepthTexture::DEFAULT_FORMAT,
+            1,
+        );
+
+        let mut renderer = Renderer {
+            instance,
+            surface,
+            surface_config,
+            adapter,
+            device,
+            queue,
+            device_lost,
+            config,
+            capture_requested: false,
+            pending_capture: None,
+            clear_color: Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            offscreen_texture: None,
+            depth_texture,
+            sample_count: 1,
+            msaa_view: None,
+            start_time: Instant::now(),
+            view_uniform,
+            view_buffer,
+            view_bind_group_layout,
+            view_bind_group,
+            object_buffer,
+            pipeline_cache: Default::default(),
+            material_bind_groups: Default::default(),
+            shader_library: Default::default(),
+            render_queue: Default::default(),
+        };
+
+        let requested = renderer.config.sample_count;
+        let sample_count = renderer
+            .supported_sample_counts()
+            .into_iter()
+            .filter(|&count| count <= requested)
+            .max()
+            .unwrap_or(1);
+        if sample_count != requested {
+            warn!("{requested}x MSAA is not supported, using {sample_count}x");
+        }
+        renderer.set_sample_count(sample_count)?;
+
+        Ok(renderer)
+    }
+
+    async fn request_device(
+        adapter: &Adapter,
+        config: &RendererConfig,
+    ) -> crate::Result<(Device, Queue, Arc<AtomicBool>)> {
+        // Built-in materials pass their factors through push constants.
+        // Sample counts other than 1 and 4 need adapter specific format features.
+        let required_features = config.features()?
+            | adapter.features()
+                & (wgpu::Features::PUSH_CONSTANTS | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let mut required_limits = config.limits.clone().unwrap_or_default();
+        if required_limits.max_push_constant_size == 0 {
+            required_limits.max_push_constant_size = adapter.limits().max_push_constant_size.min(128);
+        }
+
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
+                required_features,
+                required_limits,
                 memory_hints: Default::default(),
                 trace: wgpu::Trace::Off,
             })
             .await?;
-        
-        let camera_uniform = CameraUniform::new();
-        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: cast_slice(&[camera_uniform]),
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping a device after recovery reports `Destroyed`; only driver losses matter.
+            if reason == DeviceLostReason::Unknown {
+                error!("GPU device lost: {message}");
+                lost.store(true, Ordering::Release);
+            }
+        });
+        device.on_uncaptured_error(Box::new(|err| error!("Uncaptured wgpu error: {err}")));
+
+        Ok((device, queue, device_lost))
+    }
+
+    fn create_view_resources(device: &Device, view_uniform: &ViewUniform) -> (Buffer, BindGroupLayout, BindGroup) {
+        let view_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("View Buffer"),
+            contents: cast_slice(&[*view_uniform]),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
-        
-        let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Camera Bind Group Layout"),
+
+        let view_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("View Bind Group Layout"),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: Some(NonZeroU64::new(size_of::<CameraUniform>() as u64).unwrap()),
+                        min_binding_size: Some(NonZeroU64::new(size_of::<ViewUniform>() as u64).unwrap()),
                     },
                     count: None,
                 }
             ],
         });
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Camera Bind Group"),
-            layout: &camera_bind_group_layout,
+        let view_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("View Bind Group"),
+            layout: &view_bind_group_layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: camera_buffer.as_entire_binding()
+                    resource: view_buffer.as_entire_binding()
                 }
             ],
         });
 
-        Ok(Renderer {
-            instance,
-            surface,
-            surface_config,
-            adapter,
-            device,
-            queue,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group_layout,
-            camera_bind_group,
-            pipeline_cache: Default::default(),
-            render_queue: Default::default(),
+        (view_buffer, view_bind_group_layout, view_bind_group)
+    }
+
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// Requests a new device (and adapter, if the old one is gone) and rebuilds every
+    /// resource the renderer owns. Scene resources are rebuilt separately with
+    /// `Scene::recreate_gpu_resources`.
+    pub async fn recover_device(&mut self) -> crate::Result<()> {
+        let (device, queue, device_lost) = match Self::request_device(&self.adapter, &self.config).await {
+            Ok(device) => device,
+            Err(err) => {
+                warn!("Failed to recreate device on the current adapter: {err}");
+                self.adapter = Self::select_adapter(&self.instance, &self.config, self.surface.as_ref()).await?;
+                Self::request_device(&self.adapter, &self.config).await?
+            }
+        };
+        info!("Recreated GPU device on {}", self.adapter.get_info().name);
+
+        self.device = device;
+        self.queue = queue;
+        self.device_lost = device_lost;
+        (self.view_buffer, self.view_bind_group_layout, self.view_bind_group) =
+            Self::create_view_resources(&self.device, &self.view_uniform);
+        self.object_buffer = ObjectBuffer::new(&self.device);
+        self.pipeline_cache = PipelineCache::default();
+        self.material_bind_groups.clear();
+        self.render_queue.clear();
+
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => self.offscreen_texture = Some(self.create_offscreen_texture()),
+        }
+        if !self.supported_sample_counts().contains(&self.sample_count) {
+            warn!("{}x MSAA is not supported by the new device, rendering without multisampling", self.sample_count);
+            self.sample_count = 1;
+            self.config.sample_count = 1;
+        }
+        self.depth_texture = DepthTexture::new(
+            &self.device,
+            self.surface_config.width,
+            self.surface_config.height,
+            self.depth_texture.format,
+            self.sample_count,
+        );
+        self.msaa_view = self.create_msaa_view();
+
+        Ok(())
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => self.offscreen_texture = Some(self.create_offscreen_texture()),
+        }
+        self.depth_texture = DepthTexture::new(
+            &self.device,
+            width,
+            height,
+            self.depth_texture.format,
+            self.sample_count,
+        );
+        self.msaa_view = self.create_msaa_view();
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        let color = self.adapter.get_texture_format_features(self.surface_config.format);
+        let depth = self.adapter.get_texture_format_features(self.depth_texture.format);
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| color.flags.sample_count_supported(count) && depth.flags.sample_count_supported(count))
+            .collect()
+    }
+
+    pub fn set_sample_count(&mut self, sample_count: u32) -> crate::Result<()> {
+        if !self.supported_sample_counts().contains(&sample_count) {
+            return Err(NimbusError::UnsupportedSampleCount(sample_count));
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        self.sample_count = sample_count;
+        self.config.sample_count = sample_count;
+        self.depth_texture = DepthTexture::new(
+            &self.device,
+            self.surface_config.width,
+            self.surface_config.height,
+            self.depth_texture.format,
+            sample_count,
+        );
+        self.msaa_view = self.create_msaa_view();
+        Ok(())
+    }
+
+    fn create_msaa_view(&self) -> Option<TextureView> {
+        if self.sample_count == 1 {
+            return None;
+        }
+
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: Some("Multisampled Color Texture"),
+            size: Extent3d {
+                width: self.surface_config.width.max(1),
+                height: self.surface_config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: self.sample_count,
+            dimension: TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&TextureViewDescriptor::default()))
+    }
+
+    pub fn depth_format(&self) -> TextureFormat {
+        self.depth_texture.format
+    }
+
+    pub fn set_depth_format(&mut self, format: TextureFormat) -> crate::Result<()> {
+        let features = self.adapter.get_texture_format_features(format);
+        // Every pipeline tests and writes depth, so stencil-only formats don't work.
+        if !format.has_depth_aspect()
+            || !self.device.features().contains(format.required_features())
+            || !features.allowed_usages.contains(TextureUsages::RENDER_ATTACHMENT)
+            || !features.flags.sample_count_supported(self.sample_count)
+        {
+            return Err(NimbusError::UnsupportedDepthFormat {
+                format,
+                sample_count: self.sample_count,
+            });
+        }
+        if format == self.depth_texture.format {
+            return Ok(());
+        }
+
+        self.depth_texture = DepthTexture::new(
+            &self.device,
+            self.surface_config.width,
+            self.surface_config.height,
+            format,
+            self.sample_count,
+        );
+        self.pipeline_cache.clear();
+        Ok(())
+    }
+
+    fn create_offscreen_texture(&self) -> Texture {
+        self.device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen Color Texture"),
+            size: Extent3d {
+                width: self.surface_config.width,
+                height: self.surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: self.surface_config.usage,
+            view_formats: &[],
         })
     }
 
     pub fn begin_frame(&mut self, frame_context: &mut FrameContext) -> crate::Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
+        if self.is_device_lost() {
+            return Err(NimbusError::DeviceLost);
+        }
+        self.reload_shaders();
+
+        let view = match &self.surface {
+            Some(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+                frame_context.output = Some(output);
+                view
+            }
+            None => self
+                .offscreen_texture
+                .as_ref()
+                .unwrap()
+                .create_view(&TextureViewDescriptor::default()),
+        };
         let encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
@@ -135,12 +520,19 @@ impl<'window> Renderer<'window> {
             });
         frame_context.view = Some(view);
         frame_context.encoder = Some(encoder);
-        frame_context.output = Some(output);
-        
+
         Ok(())
     }
 
     pub fn end_frame(&mut self, frame_context: &mut FrameContext) {
+        self.object_buffer.write(
+            &self.device,
+            &self.queue,
+            self.render_queue
+                .iter()
+                .map(|item| ObjectUniform::new(item.model_matrix)),
+        );
+
         let mut main_render_pass =
             frame_context
                 .encoder
@@ -149,66 +541,381 @@ impl<'window> Renderer<'window> {
                 .begin_render_pass(&RenderPassDescriptor {
                     label: Some("Main Render Pass"),
                     color_attachments: &[Some(RenderPassColorAttachment {
-                        view: frame_context.view.as_ref().unwrap(),
+                        view: self.msaa_view.as_ref().unwrap_or(frame_context.view.as_ref().unwrap()),
                         depth_slice: None,
-                        resolve_target: None,
+                        resolve_target: self.msaa_view.as_ref().and(frame_context.view.as_ref()),
                         ops: Operations {
-                            load: LoadOp::Clear(Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
-                            store: StoreOp::Store,
+                            load: LoadOp::Clear(self.clear_color),
+                            // Only the resolved frame is needed after the pass.
+                            store: match self.msaa_view {
+                                Some(_) => StoreOp::Discard,
+                                None => StoreOp::Store,
+                            },
                         },
                     })],
-                    depth_stencil_attachment: None,
+                    depth_stencil_attachment: Some(self.depth_texture.attachment(self.config.reversed_z)),
                     timestamp_writes: None,
                     occlusion_query_set: None,
                 });
 
-        for drawable in self.render_queue.iter() {
-            let pipeline =
-                self.pipeline_cache
-                    .get_or_create(&drawable, &self.device, &self.surface_config);
+        for (index, item) in self.render_queue.iter().enumerate() {
+            let mut key = PipelineKey::new(
+                &item.mesh,
+                &item.material,
+                self.surface_config.format,
+                self.depth_texture.format,
+                self.sample_count,
+                self.config.reversed_z,
+            );
+            // Library materials always draw with the current module of their permutation,
+            // so reloaded shaders apply without touching the materials.
+            if let Some(permutation) = &item.material.shader_permutation {
+                let module = match self.pipeline_cache.get_or_create_shader(
+                    &item.material.name,
+                    permutation,
+                    &self.shader_library,
+                    &self.device,
+                ) {
+                    Ok(module) => module,
+                    Err(err) => {
+                        error!("Skipping drawable: {err}");
+                        continue;
+                    }
+                };
+                key.vertex_shader = module.clone();
+                key.fragment_shader = module;
+            }
+            let pipeline = match self.pipeline_cache.get_or_create(
+                &key,
+                &item.material,
+                &self.device,
+                &[&self.view_bind_group_layout, &self.object_buffer.bind_group_layout],
+            ) {
+                Ok(Some(pipeline)) => pipeline,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Skipping drawable: {err}");
+                    continue;
+                }
+            };
+            let material_bind_group = match self.material_bind_groups.get_or_create(
+                &item.material,
+                &pipeline.material_bind_group_layout,
+                &self.device,
+            ) {
+                Ok(Some(bind_group)) => bind_group,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Skipping drawable: {err}");
+                    continue;
+                }
+            };
             main_render_pass.set_pipeline(&pipeline.render_pipeline);
-            main_render_pass.set_bind_group(0, &pipeline.material_bind_group, &[]);
-            main_render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            main_render_pass.set_bind_group(0, material_bind_group, &[]);
+            main_render_pass.set_bind_group(1, &self.view_bind_group, &[]);
+            main_render_pass.set_bind_group(2, &self.object_buffer.bind_group, &[self.object_buffer.offset(index)]);
+
+            let push_constant_data = item.material.push_constant_data();
+            for range in item.material.ty.push_constant_ranges() {
+                if let Some(data) = push_constant_data.get(range.range.start as usize..range.range.end as usize) {
+                    main_render_pass.set_push_constants(range.stages, range.range.start, data);
+                }
+            }
 
-            main_render_pass.set_vertex_buffer(0, drawable.mesh.vertex_buffer.slice(..));
-            match &drawable.mesh.index_buffer {
+            main_render_pass.set_vertex_buffer(0, item.mesh.vertex_buffer.slice(..));
+            match &item.mesh.index_buffer {
                 None => {
-                    main_render_pass.draw(0..drawable.mesh.vertex_count, 0..1);
+                    main_render_pass.draw(0..item.mesh.vertex_count, 0..1);
                 }
                 Some(index_buffer) => {
-                    main_render_pass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint16);
-                    main_render_pass.draw_indexed(0..drawable.mesh.index_count, 0, 0..1)
+                    main_render_pass.set_index_buffer(index_buffer.slice(..), item.mesh.index_format);
+                    main_render_pass.draw_indexed(0..item.mesh.index_count, 0, 0..1)
                 }
             }
         }
 
         drop(main_render_pass);
 
-        let encoder = frame_context.encoder.take().unwrap();
-        let output = frame_context.output.take().unwrap();
+        let mut encoder = frame_context.encoder.take().unwrap();
+        if std::mem::take(&mut self.capture_requested) {
+            let texture = match &frame_context.output {
+                Some(output) => &output.texture,
+                None => self.offscreen_texture.as_ref().unwrap(),
+            };
+            self.pending_capture = Some(self.encode_readback(&mut encoder, texture));
+        }
         self.queue.submit(Some(encoder.finish()));
-        output.present();
+        if let Some(output) = frame_context.output.take() {
+            output.present();
+        }
+        frame_context.view = None;
 
         self.render_queue.clear();
+        self.material_bind_groups.prune();
     }
 
-    pub fn submit(&mut self, drawable: Drawable) {
-        self.render_queue.push(drawable)
+    /// Reads the offscreen target back as tightly packed RGBA8. HDR frames are clamped
+    /// and sRGB encoded.
+    pub fn read_frame(&self) -> crate::Result<Vec<u8>> {
+        let texture = self
+            .offscreen_texture
+            .as_ref()
+            .ok_or(NimbusError::NoOffscreenTarget)?;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        let readback = self.encode_readback(&mut encoder, texture);
+        self.queue.submit(Some(encoder.finish()));
+        self.finish_readback(readback)
+    }
+
+    /// Copies the next frame into a readback buffer before it is presented; fetch the
+    /// pixels with `take_capture` after `end_frame`. Captures use the same RGBA8 layout
+    /// as `read_frame`.
+    pub fn capture_next_frame(&mut self) -> crate::Result<()> {
+        if !self.surface_config.usage.contains(TextureUsages::COPY_SRC) {
+            return Err(NimbusError::CaptureUnsupported);
+        }
+        self.capture_requested = true;
+        Ok(())
+    }
+
+    pub fn take_capture(&mut self) -> crate::Result<Option<Vec<u8>>> {
+        self.pending_capture
+            .take()
+            .map(|readback| self.finish_readback(readback))
+            .transpose()
     }
-    
+
+    fn encode_readback(&self, encoder: &mut CommandEncoder, texture: &Texture) -> Readback {
+        let width = texture.width();
+        let height = texture.height();
+        let bytes_per_pixel = texture.format().block_copy_size(None).unwrap_or(4);
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Readback {
+            buffer,
+            format: texture.format(),
+            padded_bytes_per_row,
+            unpadded_bytes_per_row,
+        }
+    }
+
+    fn finish_readback(&self, readback: Readback) -> crate::Result<Vec<u8>> {
+        if !matches!(
+            readback.format,
+            TextureFormat::Rgba8Unorm
+                | TextureFormat::Rgba8UnormSrgb
+                | TextureFormat::Bgra8Unorm
+                | TextureFormat::Bgra8UnormSrgb
+                | TextureFormat::Rgba16Float
+        ) {
+            return Err(NimbusError::UnsupportedReadbackFormat(readback.format));
+        }
+
+        let (sender, receiver) = channel();
+        let slice = readback.buffer.slice(..);
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(PollType::Wait)?;
+        receiver.recv().map_err(|_| NimbusError::ReadbackCancelled)??;
+
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity(mapped.len());
+        for row in mapped.chunks(readback.padded_bytes_per_row as usize) {
+            let row = &row[..readback.unpadded_bytes_per_row as usize];
+            match readback.format {
+                TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+                    pixels.extend(row.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]));
+                }
+                TextureFormat::Rgba16Float => {
+                    pixels.extend(row.chunks_exact(8).flat_map(|p| {
+                        let channel = |i: usize| f16_to_f32(u16::from_le_bytes([p[i], p[i + 1]]));
+                        [
+                            encode_srgb(channel(0)),
+                            encode_srgb(channel(2)),
+                            encode_srgb(channel(4)),
+                            (channel(6).clamp(0.0, 1.0) * 255.0).round() as u8,
+                        ]
+                    }));
+                }
+                _ => pixels.extend_from_slice(row),
+            }
+        }
+        drop(mapped);
+        readback.buffer.unmap();
+
+        Ok(pixels)
+    }
+
+    pub(crate) fn shader_module(&mut self, material: &str, permutation: &ShaderPermutation) -> crate::Result<ShaderModule> {
+        self.pipeline_cache
+            .get_or_create_shader(material, permutation, &self.shader_library, &self.device)
+    }
+
+    /// Directory holding the built-in WGSL sources, for watching them during development.
+    pub fn builtin_shader_dir() -> &'static Path {
+        ShaderLibrary::builtin_dir()
+    }
+
+    /// Registers and watches every `.wgsl` file in `dir`, replacing library sources with
+    /// the same name. Changes are picked up by `reload_shaders`.
+    pub fn add_shader_directory(&mut self, dir: impl AsRef<Path>) -> io::Result<usize> {
+        self.shader_library.add_directory(dir)
+    }
+
+    pub fn pipeline_stats(&self) -> PipelineCacheStats {
+        self.pipeline_cache.stats()
+    }
+
+    pub fn reset_pipeline_stats(&mut self) {
+        self.pipeline_cache.reset_stats();
+    }
+
+    pub fn set_shader_define(&mut self, name: &str, value: impl Into<String>) -> usize {
+        if !self.shader_library.set_define(name, value) {
+            return 0;
+        }
+        self.pipeline_cache
+            .reload_all_shaders(&self.shader_library, &self.device)
+    }
+
+    pub fn reload_shaders(&mut self) -> usize {
+        let changed = self.shader_library.poll_changes();
+        if changed.is_empty() {
+            return 0;
+        }
+        self.pipeline_cache
+            .reload_shaders(&changed, &self.shader_library, &self.device)
+    }
+
+    pub(crate) fn material_shaders(
+        &mut self,
+        material: &str,
+        ty: &MaterialType,
+        alpha_mode: AlphaMode,
+    ) -> crate::Result<Option<MaterialShaders>> {
+        let Some(permutation) = ShaderPermutation::for_material(ty, alpha_mode) else {
+            return Ok(None);
+        };
+        let module = self.shader_module(material, &permutation)?;
+        Ok(Some(MaterialShaders {
+            vertex_shader: module.clone(),
+            fragment_shader: module,
+            permutation,
+        }))
+    }
+
+    /// Queues `drawable` for the current frame. Only its mesh and material handles are
+    /// retained, so submitting is cheap.
+    pub fn submit(&mut self, drawable: &Drawable, model_matrix: Matrix4<f32>) {
+        self.render_queue.push(DrawItem {
+            mesh: drawable.mesh.clone(),
+            material: drawable.material.clone(),
+            model_matrix,
+        })
+    }
+
+    pub fn reversed_z(&self) -> bool {
+        self.config.reversed_z
+    }
+
+    /// Switches every pipeline between standard and reversed-Z depth. Cameras don't need
+    /// to change; `submit_camera` sets the depth direction of their projection to match.
+    pub fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.config.reversed_z = reversed_z;
+    }
+
     pub fn submit_camera(&mut self, camera: &Camera) {
-        self.camera_uniform.update_view_proj(camera);
+        let mut camera = *camera;
+        camera.set_projection(camera.projection().with_reversed_z(self.config.reversed_z));
+        self.view_uniform.update(
+            &camera,
+            self.surface_config.width,
+            self.surface_config.height,
+            self.start_time.elapsed().as_secs_f32(),
+        );
+        self.queue
+            .write_buffer(&self.view_buffer, 0, cast_slice(&[self.view_uniform]));
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
     }
 }
 
+/// Clamps a linear color channel to [0, 1] and applies the sRGB transfer function.
+fn encode_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let srgb = match linear <= 0.0031308 {
+        true => linear * 12.92,
+        false => 1.055 * linear.powf(1.0 / 2.4) - 0.055,
+    };
+    (srgb * 255.0).round() as u8
+}
+
 #[derive(Default)]
 pub struct FrameContext {
     encoder: Option<CommandEncoder>,
     view: Option<TextureView>,
     output: Option<SurfaceTexture>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_floats_decode_and_encode_to_srgb() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3800), 0.5);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+
+        assert_eq!(encode_srgb(-1.0), 0);
+        assert_eq!(encode_srgb(0.5), 188);
+        assert_eq!(encode_srgb(4.0), 255);
+    }
+}
@@ -1,20 +1,89 @@
-use cgmath::{Matrix4, SquareMatrix};
-use crate::render::camera::Camera;
+use cgmath::{Matrix4, One, Point3, Quaternion, SquareMatrix, Transform, Vector3, Zero};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::error;
+use crate::render::camera::{Camera, Projection};
 use crate::render::drawable::Drawable;
+use crate::render::material::{Material, MaterialId, TextureUploads};
+use crate::render::mesh::Mesh;
 use crate::render::renderer::{FrameContext, Renderer};
 
+/// Local translation, rotation and scale of a node, applied in that order from the
+/// outside in (`T * R * S`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl NodeTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
 pub struct SceneNode {
     pub name: Option<String>,
-    pub local_transform: Matrix4<f32>,
     pub drawable: Option<Drawable>,
+    /// Projection of an authored camera looking down the node's -Z axis.
+    pub camera: Option<Projection>,
     pub children: Vec<usize>,
     pub parent: Option<usize>,
+
+    local_transform: NodeTransform,
+    world_transform: Matrix4<f32>,
+    // Set on a node and all of its descendants whenever their world transform is stale.
+    dirty: bool,
+}
+
+impl SceneNode {
+    pub fn new(name: Option<String>, local_transform: NodeTransform) -> Self {
+        Self {
+            name,
+            drawable: None,
+            camera: None,
+            children: vec![],
+            parent: None,
+            local_transform,
+            world_transform: Matrix4::identity(),
+            dirty: true,
+        }
+    }
+
+    pub fn local_transform(&self) -> &NodeTransform {
+        &self.local_transform
+    }
 }
 
 pub struct Scene {
     pub nodes: Vec<SceneNode>,
     pub root_nodes: Vec<usize>,
-    pub camera: Camera
+    /// Camera the scene is rendered with.
+    pub camera: Camera,
+    /// Nodes carrying an authored camera.
+    pub cameras: Vec<usize>,
+    /// Index into `cameras` of the camera driving `camera`, if any.
+    pub active_camera: Option<usize>,
+    transforms_dirty: bool,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scene {
@@ -22,44 +91,283 @@ impl Scene {
         Self {
             nodes: vec![],
             root_nodes: vec![],
-            camera: Camera::default()
+            camera: Camera::default(),
+            cameras: vec![],
+            active_camera: None,
+            transforms_dirty: false,
         }
     }
 
     pub fn add_node(&mut self, node: SceneNode) -> usize {
         let index = self.nodes.len();
         self.nodes.push(node);
+        self.transforms_dirty = true;
         index
     }
 
     pub fn add_child(&mut self, parent: usize, child: usize) {
         self.nodes[parent].children.push(child);
         self.nodes[child].parent = Some(parent);
+        self.mark_dirty(child);
+    }
+
+    pub fn set_local_transform(&mut self, node: usize, transform: NodeTransform) {
+        self.nodes[node].local_transform = transform;
+        self.mark_dirty(node);
+    }
+
+    /// World matrix of `node`, refreshing it and any stale ancestors first.
+    pub fn world_transform(&mut self, node: usize) -> Matrix4<f32> {
+        // Dirty flags cover whole subtrees, so the first clean ancestor is up to date.
+        let mut stale = vec![];
+        let mut current = Some(node);
+        while let Some(index) = current
+            && self.nodes[index].dirty
+        {
+            stale.push(index);
+            current = self.nodes[index].parent;
+        }
+        for &index in stale.iter().rev() {
+            self.update_world_transform(index);
+        }
+        self.nodes[node].world_transform
+    }
+
+    /// Recomputes the world matrix of every stale node reachable from the roots.
+    /// Does nothing if no transform changed since the last call.
+    pub fn update_transforms(&mut self) {
+        if !self.transforms_dirty {
+            return;
+        }
+        let mut stack = self.root_nodes.clone();
+        while let Some(index) = stack.pop() {
+            if self.nodes[index].dirty {
+                self.update_world_transform(index);
+            }
+            stack.extend_from_slice(&self.nodes[index].children);
+        }
+        self.transforms_dirty = false;
+    }
+
+    fn update_world_transform(&mut self, index: usize) {
+        let parent_transform = self.nodes[index]
+            .parent
+            .map_or(Matrix4::identity(), |parent| self.nodes[parent].world_transform);
+        let node = &mut self.nodes[index];
+        node.world_transform = parent_transform * node.local_transform.matrix();
+        node.dirty = false;
+    }
+
+    fn mark_dirty(&mut self, node: usize) {
+        let mut stack = vec![node];
+        while let Some(index) = stack.pop() {
+            let node = &mut self.nodes[index];
+            // A dirty node's descendants are already dirty.
+            if !node.dirty {
+                node.dirty = true;
+                stack.extend_from_slice(&node.children);
+            }
+        }
+        self.transforms_dirty = true;
     }
-    
-    pub fn render(&self, renderer: &mut Renderer, frame_ctx: &mut FrameContext) {
-        for &root in &self.root_nodes {
-            self.render_node_recursive(renderer, frame_ctx, root, Matrix4::identity());
+
+    /// Switches to the authored camera `cameras[index]`, keeping the current aspect ratio.
+    pub fn set_active_camera(&mut self, index: usize) {
+        if self.cameras.get(index).is_some_and(|&node| self.nodes[node].camera.is_some()) {
+            self.active_camera = Some(index);
+            self.update_active_camera();
         }
     }
 
-    fn render_node_recursive(
-        &self,
-        renderer: &mut Renderer,
-        frame_ctx: &mut FrameContext,
-        node_index: usize,
-        parent_transform: Matrix4<f32>,
-    ) {
-        let node = &self.nodes[node_index];
-        let world_transform = parent_transform * node.local_transform;
+    /// Moves `camera` to the current world transform of the active authored camera's
+    /// node, so animated or reparented camera nodes are followed. Does nothing while no
+    /// authored camera is active.
+    pub fn update_active_camera(&mut self) {
+        let Some(&node) = self.active_camera.and_then(|index| self.cameras.get(index)) else {
+            return;
+        };
+        let Some(projection) = self.nodes[node].camera else {
+            return;
+        };
+        self.camera = Camera::from_transform(self.world_transform(node), self.camera.aspect(), projection);
+    }
+
+    /// Rebuilds mesh buffers, textures and shaders of every drawable after
+    /// `Renderer::recover_device`. Meshes, materials and textures shared between nodes
+    /// are recreated once. A node whose material fails to recreate loses its drawable
+    /// instead of failing the whole recovery.
+    pub fn recreate_gpu_resources(&mut self, renderer: &mut Renderer) {
+        // Keyed by the old mesh's address; the old `Arc` is kept so the address can't be
+        // reused by another mesh while the pass runs.
+        let mut meshes: HashMap<*const Mesh, (Arc<Mesh>, Arc<Mesh>)> = HashMap::new();
+        let mut materials: HashMap<MaterialId, Option<Arc<Material>>> = HashMap::new();
+        let mut textures = TextureUploads::default();
+        for node in &mut self.nodes {
+            let Some(drawable) = &mut node.drawable else {
+                continue;
+            };
 
-        if let Some(mut drawable) = node.drawable.clone() {
-            drawable.model_matrix = world_transform;
-            renderer.submit(drawable);
+            let id = drawable.material.id();
+            let material = materials
+                .entry(id)
+                .or_insert_with(|| {
+                    let mut material = Material::clone(&drawable.material);
+                    match material.recreate(renderer, &mut textures) {
+                        Ok(()) => Some(Arc::new(material)),
+                        Err(err) => {
+                            error!("Dropping drawables with material {}: {err}", material.name);
+                            None
+                        }
+                    }
+                })
+                .clone();
+            let Some(material) = material else {
+                node.drawable = None;
+                continue;
+            };
+            drawable.material = material;
+
+            let (_, mesh) = meshes.entry(Arc::as_ptr(&drawable.mesh)).or_insert_with(|| {
+                let mut mesh = Mesh::clone(&drawable.mesh);
+                mesh.recreate(&renderer.device);
+                (drawable.mesh.clone(), Arc::new(mesh))
+            });
+            drawable.mesh = mesh.clone();
         }
+    }
+
+    /// World-space axis-aligned bounds of every mesh vertex in the scene.
+    pub fn bounds(&mut self) -> Option<(Point3<f32>, Point3<f32>)> {
+        self.update_transforms();
+
+        let mut bounds: Option<(Point3<f32>, Point3<f32>)> = None;
+        let mut stack = self.root_nodes.clone();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if let Some(drawable) = &node.drawable {
+                for vertex in drawable.mesh.vertices.iter() {
+                    let point = node.world_transform.transform_point(Point3::from(vertex.position));
+                    let (min, max) = bounds.get_or_insert((point, point));
+                    *min = Point3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+                    *max = Point3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+                }
+            }
+
+            stack.extend_from_slice(&node.children);
+        }
+
+        bounds
+    }
+
+    pub fn render(&mut self, renderer: &mut Renderer, _frame_ctx: &mut FrameContext) {
+        self.update_transforms();
+
+        // Children are pushed in reverse so nodes are submitted in depth-first order.
+        let mut stack: Vec<usize> = self.root_nodes.iter().rev().copied().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if let Some(drawable) = &node.drawable {
+                renderer.submit(drawable, node.world_transform);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, EuclideanSpace, Rotation3, assert_abs_diff_eq};
 
-        for &child in &node.children {
-            self.render_node_recursive(renderer, frame_ctx, child, world_transform);
+    fn translated(x: f32, y: f32, z: f32) -> NodeTransform {
+        NodeTransform {
+            translation: Vector3::new(x, y, z),
+            ..Default::default()
         }
     }
+
+    /// A root with two children, returned as `(scene, root, first, second)`.
+    fn family() -> (Scene, usize, usize, usize) {
+        let mut scene = Scene::new();
+        let root = scene.add_node(SceneNode::new(None, translated(1.0, 0.0, 0.0)));
+        scene.root_nodes.push(root);
+        let first = scene.add_node(SceneNode::new(None, translated(0.0, 2.0, 0.0)));
+        let second = scene.add_node(SceneNode::new(None, translated(0.0, 0.0, 3.0)));
+        scene.add_child(root, first);
+        scene.add_child(root, second);
+        (scene, root, first, second)
+    }
+
+    #[test]
+    fn child_world_transform_follows_its_parent() {
+        let (mut scene, root, first, _) = family();
+        assert_abs_diff_eq!(
+            scene.world_transform(first),
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 0.0))
+        );
+
+        let rotated = NodeTransform {
+            rotation: Quaternion::from_angle_z(Deg(90.0)),
+            ..translated(1.0, 0.0, 0.0)
+        };
+        scene.set_local_transform(root, rotated);
+        assert_abs_diff_eq!(
+            scene.world_transform(first).transform_point(Point3::origin()),
+            Point3::new(-1.0, 0.0, 0.0),
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn querying_one_child_leaves_its_sibling_dirty() {
+        let (mut scene, root, first, second) = family();
+        scene.update_transforms();
+
+        scene.set_local_transform(root, translated(5.0, 0.0, 0.0));
+        scene.world_transform(first);
+        assert!(!scene.nodes[root].dirty);
+        assert!(!scene.nodes[first].dirty);
+        assert!(scene.nodes[second].dirty);
+        assert_abs_diff_eq!(
+            scene.world_transform(second),
+            Matrix4::from_translation(Vector3::new(5.0, 0.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn update_transforms_finishes_a_partial_update() {
+        let (mut scene, root, first, second) = family();
+        scene.update_transforms();
+
+        scene.set_local_transform(root, translated(5.0, 0.0, 0.0));
+        scene.world_transform(first);
+        scene.update_transforms();
+        assert!(scene.nodes.iter().all(|node| !node.dirty));
+        assert_abs_diff_eq!(
+            scene.nodes[first].world_transform,
+            Matrix4::from_translation(Vector3::new(5.0, 2.0, 0.0))
+        );
+        assert_abs_diff_eq!(
+            scene.nodes[second].world_transform,
+            Matrix4::from_translation(Vector3::new(5.0, 0.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn active_camera_follows_its_node() {
+        let (mut scene, root, first, _) = family();
+        scene.nodes[first].camera = Some(Projection::default());
+        scene.cameras.push(first);
+        scene.set_active_camera(0);
+        assert_abs_diff_eq!(scene.camera.eye(), Point3::new(1.0, 2.0, 0.0));
+
+        scene.set_local_transform(root, translated(5.0, 0.0, 0.0));
+        scene.update_active_camera();
+        assert_abs_diff_eq!(scene.camera.eye(), Point3::new(5.0, 2.0, 0.0));
+
+        scene.active_camera = None;
+        scene.set_local_transform(root, translated(0.0, 0.0, 0.0));
+        scene.update_active_camera();
+        assert_abs_diff_eq!(scene.camera.eye(), Point3::new(5.0, 2.0, 0.0));
+    }
 }
@@ -0,0 +1,265 @@
+use crate::render::material::{AlphaMode, MaterialType, TextureSlot};
+use crate::errors::NimbusError;
+use crate::render::preprocessor::{PreprocessError, PreprocessedSource, ShaderDefines, ShaderPreprocessor};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::warn;
+use wgpu::naga::front::wgsl;
+use wgpu::naga::valid::{Capabilities, ValidationFlags, Validator};
+use wgpu::{Device, ErrorFilter, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+const BUILTIN_SOURCES: [(&str, &str); 3] = [
+    ("common.wgsl", include_str!("shaders/common.wgsl")),
+    ("unlit.wgsl", include_str!("shaders/unlit.wgsl")),
+    ("pbr.wgsl", include_str!("shaders/pbr.wgsl")),
+];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
+pub(crate) struct MaterialShaders {
+    pub vertex_shader: ShaderModule,
+    pub fragment_shader: ShaderModule,
+    pub permutation: ShaderPermutation,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum BuiltinShader {
+    Unlit,
+    Pbr,
+}
+
+impl BuiltinShader {
+    pub fn for_material(ty: &MaterialType) -> Option<Self> {
+        match ty {
+            MaterialType::Unlit { .. } => Some(BuiltinShader::Unlit),
+            MaterialType::Pbr { .. } => Some(BuiltinShader::Pbr),
+            MaterialType::Custom(_) => None,
+        }
+    }
+
+    pub fn source_name(self) -> &'static str {
+        match self {
+            BuiltinShader::Unlit => "unlit.wgsl",
+            BuiltinShader::Pbr => "pbr.wgsl",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ShaderPermutation {
+    pub source: String,
+    pub defines: ShaderDefines,
+}
+
+impl ShaderPermutation {
+    pub fn new(source: impl Into<String>, defines: ShaderDefines) -> Self {
+        Self {
+            source: source.into(),
+            defines,
+        }
+    }
+
+    pub fn for_material(ty: &MaterialType, alpha_mode: AlphaMode) -> Option<Self> {
+        let shader = BuiltinShader::for_material(ty)?;
+        let mut defines = ty.shader_defines();
+        if let Some(define) = alpha_mode.shader_define() {
+            defines.insert(define.to_owned(), String::new());
+        }
+        Some(Self::new(shader.source_name(), defines))
+    }
+
+    pub fn label(&self) -> String {
+        let defines: Vec<&str> = self
+            .defines
+            .iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        format!("{} [{}]", self.source, defines.join(", "))
+    }
+}
+
+/// Texture bindings and samplers shared by the built-in shaders, generated from
+/// [`TextureSlot`] so the WGSL always matches the bind group layout.
+fn material_source() -> String {
+    let mut source = String::from("#include \"common.wgsl\"\n");
+    for slot in TextureSlot::ALL {
+        source.push('\n');
+        source.push_str(&slot.wgsl());
+    }
+    source
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Shader sources available to the renderer. Sources registered with `add_file` or
+/// `add_directory` are watched, and `poll_changes` reloads them when their
+/// modification time changes.
+pub(crate) struct ShaderLibrary {
+    preprocessor: ShaderPreprocessor,
+    defines: ShaderDefines,
+    files: HashMap<String, WatchedFile>,
+    last_poll: Instant,
+}
+
+impl Default for ShaderLibrary {
+    fn default() -> Self {
+        let mut preprocessor = ShaderPreprocessor::default();
+        for (name, source) in BUILTIN_SOURCES {
+            preprocessor.add_source(name, source);
+        }
+        preprocessor.add_source("material.wgsl", material_source());
+        Self {
+            preprocessor,
+            defines: ShaderDefines::new(),
+            files: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+impl ShaderLibrary {
+    pub fn preprocess(&self, permutation: &ShaderPermutation) -> Result<PreprocessedSource, PreprocessError> {
+        let mut defines = self.defines.clone();
+        defines.extend(permutation.defines.clone());
+        self.preprocessor.process(&permutation.source, &defines)
+    }
+
+    /// Sets a define applied to every permutation; permutation defines take precedence.
+    pub fn set_define(&mut self, name: impl Into<String>, value: impl Into<String>) -> bool {
+        let value = value.into();
+        self.defines.insert(name.into(), value.clone()) != Some(value)
+    }
+
+    pub fn builtin_dir() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/render/shaders"))
+    }
+
+    pub fn add_file(&mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> io::Result<()> {
+        let path = path.into();
+        let source = fs::read_to_string(&path)?;
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        let name = name.into();
+        self.preprocessor.add_source(name.clone(), source);
+        self.files.insert(name, WatchedFile { path, modified });
+        Ok(())
+    }
+
+    /// Registers every `.wgsl` file in `dir` under its file name, replacing any
+    /// source with the same name (including the built-in shaders).
+    pub fn add_directory(&mut self, dir: impl AsRef<Path>) -> io::Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "wgsl") {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|name| name.to_str()).map(str::to_owned) else {
+                continue;
+            };
+            self.add_file(name, path)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reloads watched files whose modification time changed since the last poll and
+    /// returns their names. Polls at most every `POLL_INTERVAL`.
+    pub fn poll_changes(&mut self) -> Vec<String> {
+        if self.files.is_empty() || self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (name, file) in &mut self.files {
+            let Ok(modified) = fs::metadata(&file.path).and_then(|metadata| metadata.modified()) else {
+                continue;
+            };
+            if file.modified == Some(modified) {
+                continue;
+            }
+            match fs::read_to_string(&file.path) {
+                Ok(source) => {
+                    file.modified = Some(modified);
+                    self.preprocessor.add_source(name.clone(), source);
+                    changed.push(name.clone());
+                }
+                Err(error) => warn!("Failed to reload shader {}: {error}", file.path.display()),
+            }
+        }
+        changed
+    }
+}
+
+/// Creates a shader module inside a validation error scope so invalid WGSL is
+/// reported as an error instead of reaching the device's uncaptured error handler.
+pub(crate) fn compile_shader(
+    device: &Device,
+    material: &str,
+    permutation: &ShaderPermutation,
+    source: &PreprocessedSource,
+) -> crate::Result<ShaderModule> {
+    device.push_error_scope(ErrorFilter::Validation);
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(&permutation.label()),
+        source: ShaderSource::Wgsl(source.code.as_str().into()),
+    });
+    match pollster::block_on(device.pop_error_scope()) {
+        None => Ok(module),
+        Some(error) => Err(NimbusError::ShaderCompileError {
+            material: material.to_owned(),
+            location: error_location(source).unwrap_or_else(|| permutation.label()),
+            message: error.to_string(),
+        }),
+    }
+}
+
+// wgpu reports positions in the preprocessed output; re-run naga to find the
+// failing line and map it back to the file it came from.
+fn error_location(source: &PreprocessedSource) -> Option<String> {
+    let location = match wgsl::parse_str(&source.code) {
+        Err(error) => error.location(&source.code),
+        Ok(module) => Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .err()?
+            .location(&source.code),
+    }?;
+    let mapped = source.location(location.line_number as usize)?;
+    Some(format!("{mapped}:{}", location.line_position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_shaders_validate_with_and_without_textures() {
+        let library = ShaderLibrary::default();
+        for shader in [BuiltinShader::Unlit, BuiltinShader::Pbr] {
+            for textured in [false, true] {
+                let mut defines = ShaderDefines::new();
+                for slot in TextureSlot::ALL {
+                    slot.add_defines(&mut defines);
+                    if textured {
+                        defines.insert(format!("HAS_{}_TEXTURE", slot.define_prefix()), String::new());
+                    }
+                }
+                let permutation = ShaderPermutation::new(shader.source_name(), defines);
+                let source = library.preprocess(&permutation).unwrap();
+                let module = wgsl::parse_str(&source.code)
+                    .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&source.code)));
+                Validator::new(ValidationFlags::all(), Capabilities::all())
+                    .validate(&module)
+                    .unwrap_or_else(|err| panic!("{}: {err:?}", permutation.label()));
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+struct View {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    viewport_size: vec2<f32>,
+    time: f32,
+    _padding: f32,
+}
+
+struct Object {
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+}
+
+struct MaterialFactors {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    normal_scale: f32,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    tex_coord_sets: u32,
+    alpha_mode: u32,
+    _padding: vec2<u32>,
+}
+
+@group(1) @binding(0) var<uniform> view: View;
+@group(2) @binding(0) var<uniform> object: Object;
+var<push_constant> material: MaterialFactors;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coord: vec2<f32>,
+    @location(3) tex_coord_1: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) tex_coord: vec2<f32>,
+    @location(3) tex_coord_1: vec2<f32>,
+}
+
+@vertex
+fn vertexMain(in: VertexInput) -> VertexOutput {
+    let world_position = object.model * vec4<f32>(in.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = view.view_projection * world_position;
+    out.world_position = world_position.xyz;
+    out.world_normal = (object.normal * vec4<f32>(in.normal, 0.0)).xyz;
+    out.tex_coord = in.tex_coord;
+    out.tex_coord_1 = in.tex_coord_1;
+    return out;
+}
+
+fn material_uv(slot: u32, in: VertexOutput) -> vec2<f32> {
+    if (material.tex_coord_sets & (1u << slot)) != 0u {
+        return in.tex_coord_1;
+    }
+    return in.tex_coord;
+}
+
+fn apply_alpha_mask(alpha: f32) {
+#ifdef ALPHA_MASK
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+#endif
+}
@@ -0,0 +1,97 @@
+#include "common.wgsl"
+#include "material.wgsl"
+
+const PI: f32 = 3.14159265359;
+
+// There is no light list yet, so every PBR material is lit by one sun and a flat ambient term.
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(-0.4, -1.0, -0.3);
+const LIGHT_COLOR: vec3<f32> = vec3<f32>(3.0, 3.0, 3.0);
+#ifndef AMBIENT_LIGHT
+#define AMBIENT_LIGHT vec3<f32>(0.03, 0.03, 0.03)
+#endif
+const AMBIENT_COLOR: vec3<f32> = AMBIENT_LIGHT;
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    return geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Builds a tangent frame from screen-space derivatives, so meshes need no tangent attribute.
+fn perturb_normal(normal: vec3<f32>, position: vec3<f32>, uv: vec2<f32>, sampled: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(position);
+    let dp2 = dpdy(position);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    let inv_max = inverseSqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+
+    let tangent_normal = (sampled * 2.0 - 1.0) * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    let tbn = mat3x3<f32>(tangent * inv_max, bitangent * inv_max, normal);
+    return normalize(tbn * tangent_normal);
+}
+
+@fragment
+fn fragmentMain(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = material.base_color * sample_base_color(in);
+    let metallic_roughness = sample_metallic_roughness(in);
+    let normal_sample = sample_normal(in);
+    let occlusion_sample = sample_occlusion(in);
+    let emissive_sample = sample_emissive(in);
+
+    apply_alpha_mask(base_color.a);
+
+    let metallic = clamp(material.metallic * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.roughness * metallic_roughness.g, 0.04, 1.0);
+
+    var n = normalize(in.world_normal);
+    if !front_facing {
+        n = -n;
+    }
+#ifdef HAS_NORMAL_TEXTURE
+    n = perturb_normal(n, in.world_position, material_uv(NORMAL_SLOT, in), normal_sample.rgb);
+#endif
+
+    let v = normalize(view.camera_position.xyz - in.world_position);
+    let l = normalize(-LIGHT_DIRECTION);
+    let h = normalize(v + l);
+
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let v_dot_h = max(dot(v, h), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    let f = fresnel_schlick(v_dot_h, f0);
+    let d = distribution_ggx(n_dot_h, roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+
+    let specular = d * g * f / max(4.0 * n_dot_v * n_dot_l, 1e-4);
+    let diffuse = (1.0 - f) * (1.0 - metallic) * base_color.rgb / PI;
+    let direct = (diffuse + specular) * LIGHT_COLOR * n_dot_l;
+
+    let occlusion = mix(1.0, occlusion_sample.r, material.occlusion_strength);
+    let ambient = AMBIENT_COLOR * base_color.rgb * occlusion;
+    let emissive = material.emissive * emissive_sample.rgb;
+
+    return vec4<f32>(direct + ambient + emissive, base_color.a);
+}
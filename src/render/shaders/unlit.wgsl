@@ -0,0 +1,9 @@
+#include "common.wgsl"
+#include "material.wgsl"
+
+@fragment
+fn fragmentMain(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = material.base_color * sample_base_color(in);
+    apply_alpha_mask(base_color.a);
+    return base_color;
+}
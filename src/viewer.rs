@@ -0,0 +1,321 @@
+use anyhow::{bail, Context};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+use clap::{Args, ValueEnum};
+use image::{ColorType, ExtendedColorType};
+use nimbus::{
+    AppHandler, Camera, CameraController, FlyController, FrameContext, GltfImporter, OrbitController, Renderer,
+    RendererConfig, Scene,
+};
+use std::path::{Path, PathBuf};
+use tracing::info;
+use wgpu::Color;
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Window, WindowAttributes};
+
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSpec {
+    pub eye: Point3<f32>,
+    pub target: Option<Point3<f32>>,
+}
+
+fn parse_camera(value: &str) -> Result<CameraSpec, String> {
+    let values = value
+        .split(',')
+        .map(|component| component.trim().parse::<f32>().map_err(|err| format!("{component}: {err}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [x, y, z] => Ok(CameraSpec {
+            eye: Point3::new(x, y, z),
+            target: None,
+        }),
+        [x, y, z, tx, ty, tz] => Ok(CameraSpec {
+            eye: Point3::new(x, y, z),
+            target: Some(Point3::new(tx, ty, tz)),
+        }),
+        _ => Err("expected x,y,z or x,y,z,target_x,target_y,target_z".to_owned()),
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
+pub enum ControllerKind {
+    #[default]
+    Orbit,
+    Fly,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ViewerOptions {
+    /// glTF or GLB file to open.
+    pub model: Option<PathBuf>,
+
+    /// Camera position as x,y,z, optionally followed by a target x,y,z.
+    /// Without it the camera frames the whole model.
+    #[arg(long, value_parser = parse_camera, allow_hyphen_values = true)]
+    pub camera: Option<CameraSpec>,
+
+    /// Environment image (PNG, JPEG or Radiance HDR). Its average color is used as
+    /// uniform ambient light and as the background.
+    #[arg(long)]
+    pub env: Option<PathBuf>,
+
+    /// Write the last rendered frame to this PNG file.
+    #[arg(long)]
+    pub screenshot: Option<PathBuf>,
+
+    /// Exit after rendering this many frames.
+    #[arg(long)]
+    pub frames: Option<u64>,
+
+    /// Start with this camera from the model instead of framing it; C cycles through
+    /// the model's cameras while running.
+    #[arg(long)]
+    pub scene_camera: Option<usize>,
+
+    /// How mouse and keyboard input move the camera.
+    #[arg(long, value_enum, default_value_t)]
+    pub controller: ControllerKind,
+
+    /// Render offscreen without opening a window.
+    #[arg(long)]
+    pub headless: bool,
+
+    #[arg(long, default_value_t = 1280)]
+    pub width: u32,
+
+    #[arg(long, default_value_t = 720)]
+    pub height: u32,
+}
+
+impl ViewerOptions {
+    /// Number of frames to render before exiting. A screenshot without `--frames`
+    /// is taken from the first frame.
+    pub fn frame_limit(&self) -> Option<u64> {
+        self.frames
+            .or(self.screenshot.as_ref().map(|_| 1))
+            .map(|frames| frames.max(1))
+    }
+
+    pub fn is_last_frame(&self, frame: u64) -> bool {
+        self.frame_limit().is_some_and(|limit| frame + 1 >= limit)
+    }
+
+    pub fn setup(&self, renderer: &mut Renderer) -> anyhow::Result<Scene> {
+        if let Some(path) = &self.env {
+            let [r, g, b] = average_color(path)?;
+            renderer.set_shader_define("AMBIENT_LIGHT", format!("vec3<f32>({r:.6}, {g:.6}, {b:.6})"));
+            renderer.clear_color = Color {
+                r: r as f64,
+                g: g as f64,
+                b: b as f64,
+                a: 1.0,
+            };
+        }
+
+        let mut scene = match &self.model {
+            Some(path) => GltfImporter::new(renderer)
+                .import(path)
+                .with_context(|| format!("Failed to load {}", path.display()))?,
+            None => Scene::new(),
+        };
+
+        let aspect = renderer.surface_config.width as f32 / renderer.surface_config.height.max(1) as f32;
+        scene.camera = self.camera(&mut scene, aspect);
+        Ok(scene)
+    }
+
+    fn camera(&self, scene: &mut Scene, aspect: f32) -> Camera {
+        let (center, radius) = scene_extent(scene);
+
+        let (eye, target) = match self.camera {
+            Some(CameraSpec { eye, target }) => (eye, target.unwrap_or(center)),
+            None => (center + Vector3::new(0.0, 0.5, 2.5) * radius, center),
+        };
+
+        let distance = (eye - target).magnitude();
+        Camera::new(
+            eye,
+            target,
+            Vector3::unit_y(),
+            aspect,
+            45.0,
+            (radius * 0.01).max(1e-3),
+            (distance + radius) * 2.0,
+        )
+    }
+
+    pub fn run_headless(self, config: RendererConfig) -> anyhow::Result<()> {
+        let mut renderer = pollster::block_on(Renderer::new_headless(self.width, self.height, config))?;
+        let mut viewer = Viewer::new(self);
+        let mut scene = viewer.init(&mut renderer)?;
+        if viewer.options.frame_limit().is_none() {
+            viewer.options.frames = Some(1);
+        }
+
+        let mut frame_context = FrameContext::default();
+        for frame in 0.. {
+            renderer.begin_frame(&mut frame_context)?;
+            viewer.before_frame(&mut renderer, &mut scene, frame)?;
+            renderer.submit_camera(&scene.camera);
+            scene.render(&mut renderer, &mut frame_context);
+            renderer.end_frame(&mut frame_context);
+            if !viewer.after_frame(&mut renderer, &mut scene, frame)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_capture(&self, renderer: &mut Renderer) -> anyhow::Result<()> {
+        let (Some(path), Some(pixels)) = (&self.screenshot, renderer.take_capture()?) else {
+            return Ok(());
+        };
+        let config = &renderer.surface_config;
+        save_screenshot(path, &pixels, config.width, config.height)?;
+        info!("Saved screenshot to {}", path.display());
+        Ok(())
+    }
+}
+
+pub struct Viewer {
+    options: ViewerOptions,
+    controller: Box<dyn CameraController>,
+    /// The framing camera, restored after cycling past the model's cameras.
+    free_camera: Camera,
+}
+
+impl Viewer {
+    pub fn new(options: ViewerOptions) -> Self {
+        Self {
+            options,
+            controller: Box::new(OrbitController::default()),
+            free_camera: Camera::default(),
+        }
+    }
+
+    fn next_camera(&mut self, scene: &mut Scene) {
+        let next = scene.active_camera.map_or(0, |index| index + 1);
+        if next < scene.cameras.len() {
+            if scene.active_camera.is_none() {
+                self.free_camera = scene.camera;
+            }
+            scene.set_active_camera(next);
+            let name = scene.nodes[scene.cameras[next]].name.as_deref().unwrap_or("unnamed");
+            info!("Switched to camera {next} ({name})");
+        } else if scene.active_camera.is_some() {
+            let aspect = scene.camera.aspect();
+            scene.camera = self.free_camera;
+            scene.camera.set_aspect(aspect);
+            scene.active_camera = None;
+            info!("Switched to the free camera");
+        }
+    }
+}
+
+impl AppHandler for Viewer {
+    fn window_attributes(&self) -> WindowAttributes {
+        Window::default_attributes()
+            .with_title("Nimbus Renderer")
+            .with_inner_size(PhysicalSize::new(self.options.width, self.options.height))
+            .with_resizable(true)
+    }
+
+    // Debug builds pick up edits to the built-in shaders while running from a checkout.
+    fn shader_directories(&self) -> Vec<PathBuf> {
+        let shader_dir = Renderer::builtin_shader_dir();
+        match cfg!(debug_assertions) && shader_dir.is_dir() {
+            true => vec![shader_dir.to_path_buf()],
+            false => vec![],
+        }
+    }
+
+    fn init(&mut self, renderer: &mut Renderer) -> anyhow::Result<Scene> {
+        let mut scene = self.options.setup(renderer)?;
+        self.free_camera = scene.camera;
+        if let Some(index) = self.options.scene_camera {
+            if index >= scene.cameras.len() {
+                bail!("Camera {index} requested but the model has {} cameras", scene.cameras.len());
+            }
+            scene.set_active_camera(index);
+        }
+        self.controller = match self.options.controller {
+            ControllerKind::Orbit => Box::new(OrbitController::default()),
+            // Cross the model in about two seconds regardless of its scale.
+            ControllerKind::Fly => Box::new(FlyController::new(scene_extent(&mut scene).1)),
+        };
+        Ok(scene)
+    }
+
+    fn camera_controller(&mut self) -> Option<&mut dyn CameraController> {
+        Some(self.controller.as_mut())
+    }
+
+    fn window_event(&mut self, scene: &mut Scene, event: &WindowEvent) {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.next_camera(scene);
+        }
+    }
+
+    fn before_frame(&mut self, renderer: &mut Renderer, _scene: &mut Scene, frame: u64) -> anyhow::Result<()> {
+        if self.options.screenshot.is_some() && self.options.is_last_frame(frame) {
+            renderer.capture_next_frame()?;
+        }
+        Ok(())
+    }
+
+    fn after_frame(&mut self, renderer: &mut Renderer, _scene: &mut Scene, frame: u64) -> anyhow::Result<bool> {
+        self.options.save_capture(renderer)?;
+        Ok(!self.options.is_last_frame(frame))
+    }
+}
+
+/// Center and radius of the scene's bounding sphere.
+fn scene_extent(scene: &mut Scene) -> (Point3<f32>, f32) {
+    match scene.bounds() {
+        Some((min, max)) => (min.midpoint(max), ((max - min).magnitude() / 2.0).max(1e-3)),
+        None => (Point3::origin(), 1.0),
+    }
+}
+
+fn average_color(path: &Path) -> anyhow::Result<[f32; 3]> {
+    let image = image::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    // Radiance HDR decodes to linear floats; 8 and 16 bit images are sRGB encoded.
+    let srgb = !matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+    let pixels = image.to_rgb32f();
+
+    let mut sum = [0.0f64; 3];
+    for pixel in pixels.pixels() {
+        for (total, &channel) in sum.iter_mut().zip(&pixel.0) {
+            *total += if srgb { srgb_to_linear(channel) } else { channel } as f64;
+        }
+    }
+    let count = (pixels.width() as f64 * pixels.height() as f64).max(1.0);
+    Ok(sum.map(|total| (total / count) as f32))
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn save_screenshot(path: &Path, pixels: &[u8], width: u32, height: u32) -> anyhow::Result<()> {
+    // The frame's alpha depends on the blend state of whatever was drawn last.
+    let rgba: Vec<u8> = pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2], 255]).collect();
+    image::save_buffer(path, &rgba, width, height, ExtendedColorType::Rgba8)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}